@@ -1,7 +1,7 @@
 use std::{hint::black_box, str::FromStr};
 
 use criterion::{Criterion, criterion_group, criterion_main};
-use mod_rewrite::{self, ExprGroup, Expression, Rewrite};
+use mod_rewrite::{self, ExprGroup, Expression, GroupBuilder, Rewrite, Rule};
 use pprof::criterion::{Output, PProfProfiler};
 
 fn profiled() -> Criterion {
@@ -27,22 +27,40 @@ pub fn rewrite_match_ne(g: &ExprGroup) {
 pub fn bench_rule_match(c: &mut Criterion) {
     let e = Expression::from_str("RewriteRule /static/(.*) /files/$1").unwrap();
     let g = ExprGroup::new(vec![e]);
-    c.bench_function("basic_match", |b| {
-        b.iter(|| black_box(rewrite_match(black_box(&g))))
-    });
+    c.bench_function("basic_match", |b| b.iter(|| rewrite_match(black_box(&g))));
 }
 
 pub fn bench_rule_match_ne(c: &mut Criterion) {
     let e = Expression::from_str("RewriteRule /static/(.*) /files/$1 [NE]").unwrap();
     let g = ExprGroup::new(vec![e]);
     c.bench_function("basic_match_ne", |b| {
-        b.iter(|| black_box(rewrite_match_ne(black_box(&g))))
+        b.iter(|| rewrite_match_ne(black_box(&g)))
+    });
+}
+
+pub fn bench_many_rules(c: &mut Criterion) {
+    let g = (0..200)
+        .map(|i| format!("^/section{i}/(.*)$ /files/{i}/$1 [L]"))
+        .fold(GroupBuilder::new(), |g, rule| {
+            g.rule(Rule::from_str(&rule).unwrap())
+        })
+        .build();
+    c.bench_function("many_rules_match", |b| {
+        b.iter(|| {
+            assert!(matches!(
+                black_box(&g).rewrite("/section199/page"),
+                Ok(Rewrite::Uri(uri)) if uri == "/files/199/page",
+            ))
+        })
+    });
+    c.bench_function("many_rules_miss", |b| {
+        b.iter(|| black_box(&g).rewrite("/unmatched/page"))
     });
 }
 
 criterion_group!(
     name = benches;
     config = profiled();
-    targets = bench_rule_match, bench_rule_match_ne
+    targets = bench_rule_match, bench_rule_match_ne, bench_many_rules
 );
 criterion_main!(benches);
@@ -4,16 +4,46 @@
 //! Designed as a subset of [official](https://httpd.apache.org/docs/current/mod/mod_rewrite.html#rewritecond)
 //! `RewriteCond` back-references.
 
-use std::{collections::HashMap, fmt::Debug, io, net::ToSocketAddrs};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    fmt::Debug,
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::Path,
+    sync::Arc,
+};
 
 use once_cell::sync::Lazy;
+use percent_encoding::percent_decode_str;
 use regex_automata::meta::Regex;
 
-static MATCHER: Lazy<Regex> = Lazy::new(|| Regex::new(r"%\{\w+\}").unwrap());
+use super::matcher::FileTest;
+use crate::extra;
+use crate::map::Maps;
+
+static MATCHER: Lazy<Regex> = Lazy::new(|| Regex::new(r"%\{[\w:\-]+\}").unwrap());
+static BACKREF_MATCHER: Lazy<Regex> = Lazy::new(|| Regex::new(r"%\{[\w:\-]+\}|%[0-9]").unwrap());
+
+/// Pseudo-variables with fixed values since the features they
+/// describe are never performed by the engine.
+///
+/// - `IS_SUBREQ` is always `false` as subrequests are never made.
+/// - `REMOTE_IDENT` is always empty as identd lookups are never made.
+const CONSTANTS: &[(&str, &str)] = &[("IS_SUBREQ", "false"), ("REMOTE_IDENT", "")];
+
+/// Iterate over the names of all `%{VARIABLE}` references within
+/// an expression.
+pub(crate) fn variables(expr: &str) -> impl Iterator<Item = &str> {
+    MATCHER
+        .find_iter(expr)
+        .map(|m| &expr[m.start() + 2..m.end() - 1])
+}
 
 macro_rules! get {
     ($key:expr) => {
-        Some($key.as_ref().map(|s| s.as_str()).unwrap_or(""))
+        $key.as_deref()
     };
 }
 
@@ -33,19 +63,131 @@ macro_rules! setter {
 /// and pass it into [`Engine::rewrite_ctx`](crate::Engine::rewrite_ctx)
 /// in order to pass variables to [`Condition`](crate::Condition)
 /// rules.
+///
+/// Providers are consulted in the order they were added, so custom
+/// variables can be supplied alongside the built-in contexts.
+///
+/// # Example
+///
+/// ```
+/// use mod_rewrite::context::{ContextProvider, EngineCtx, RequestCtx};
+///
+/// struct Geo;
+///
+/// impl ContextProvider for Geo {
+///     fn fill(&mut self, key: &str) -> Option<&str> {
+///         match key {
+///             "GEO_COUNTRY" => Some("NL"),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let mut ctx = EngineCtx::default()
+///     .with_ctx(RequestCtx::default())
+///     .with_ctx(Geo);
+/// assert_eq!(ctx.fill("GEO_COUNTRY"), "NL");
+/// ```
 pub trait ContextProvider {
     fn fill(&mut self, key: &str) -> Option<&str>;
 }
 
+/// Fixed variables keyed by name.
+impl ContextProvider for HashMap<String, String> {
+    #[inline]
+    fn fill(&mut self, key: &str) -> Option<&str> {
+        self.get(key).map(|v| v.as_str())
+    }
+}
+
+/// File attributes used to evaluate `RewriteCond` file-tests.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileInfo {
+    pub is_dir: bool,
+    pub is_file: bool,
+    pub is_symlink: bool,
+    pub size: u64,
+    /// Never set by [`FsResolver`] on non-unix targets.
+    pub executable: bool,
+}
+
+/// Abstraction for filesystem lookups made by `RewriteCond` file-tests
+/// such as `-f` and `-d`.
+///
+/// Supply an implementation to [`EngineCtx::with_resolver`] to replace
+/// the default [`FsResolver`].
+pub trait FileResolver {
+    /// Return the attributes of the file at `path` or `None`
+    /// if it does not exist.
+    fn stat(&self, path: &str) -> Option<FileInfo>;
+}
+
+/// Default [`FileResolver`] reading from the local filesystem.
+#[derive(Clone, Debug, Default)]
+pub struct FsResolver;
+
+impl FileResolver for FsResolver {
+    fn stat(&self, path: &str) -> Option<FileInfo> {
+        let path = Path::new(path);
+        let link = path.symlink_metadata().ok()?;
+        let meta = path.metadata().ok();
+        Some(FileInfo {
+            is_dir: meta.as_ref().is_some_and(|m| m.is_dir()),
+            is_file: meta.as_ref().is_some_and(|m| m.is_file()),
+            is_symlink: link.is_symlink(),
+            size: meta.as_ref().map(|m| m.len()).unwrap_or(0),
+            executable: meta.is_some_and(|m| is_executable(&m)),
+        })
+    }
+}
+
+/// Returns true if any execute permission bit is set.
+#[cfg(unix)]
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+/// Execute permissions are unix-specific, so files are never executable.
+#[cfg(not(unix))]
+fn is_executable(_meta: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Abstraction for reverse lookups of the remote address used to assign
+/// `REMOTE_HOST` like Apache's `HostnameLookups`.
+///
+/// Only invoked by [`RequestCtx::resolve_remote_host`]. Implementations
+/// are expected to answer from data already at hand, such as a cache
+/// populated asynchronously by the caller, rather than blocking on DNS.
+pub trait HostResolver {
+    /// Return the hostname of the address or `None` if it is unknown.
+    fn resolve(&self, ip: IpAddr) -> Option<String>;
+}
+
 /// Global Context used for variable replacement in
 /// [`Condition`](super::Condition) expressions.
 #[derive(Default)]
-pub struct EngineCtx<'a>(Vec<Box<dyn ContextProvider + 'a>>);
+pub struct EngineCtx<'a> {
+    providers: Vec<Box<dyn ContextProvider + 'a>>,
+    resolver: Option<Box<dyn FileResolver + 'a>>,
+    file_tests: HashMap<(FileTest, String), bool>,
+    captures: Vec<String>,
+    env: HashMap<String, String>,
+    path: String,
+    derived: String,
+    missing: bool,
+    vary: Vec<String>,
+    segments: Vec<String>,
+    query: Vec<(String, String)>,
+    query_all: HashMap<String, String>,
+    maps: Option<Arc<Maps>>,
+}
 
 impl<'a> EngineCtx<'a> {
     /// Assign new sub-context to the complete [`EngineCtx`]
     pub fn push_ctx(&mut self, ctx: impl ContextProvider + 'a) -> &mut Self {
-        self.0.push(Box::new(ctx));
+        self.providers.push(Box::new(ctx));
         self
     }
 
@@ -65,28 +207,395 @@ impl<'a> EngineCtx<'a> {
         self.with_ctx(DateCtx::new())
     }
 
+    /// Replace the [`FileResolver`] used by file-test conditions
+    /// when building [`EngineCtx`]
+    pub fn with_resolver(mut self, resolver: impl FileResolver + 'a) -> Self {
+        self.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Evaluate a file-test against the expanded path.
+    ///
+    /// Results are cached until the next [`EngineCtx::reset`] so identical
+    /// tests repeated across groups only hit the filesystem once per rewrite.
+    pub(crate) fn file_test(&mut self, test: &FileTest, path: &str) -> bool {
+        let key = (test.clone(), path.to_owned());
+        if let Some(result) = self.file_tests.get(&key) {
+            return *result;
+        }
+        let info = match &self.resolver {
+            Some(resolver) => resolver.stat(path),
+            None => FsResolver.stat(path),
+        };
+        let result = test.matches(info.as_ref());
+        self.file_tests.insert(key, result);
+        result
+    }
+
     /// Return the equivalent value associated with the specified
     /// variable expression.
+    ///
+    /// Variables not supplied by any sub-context expand to an empty string,
+    /// except for a few constant pseudo-variables such as `IS_SUBREQ`
+    /// which always expands to `false`.
+    ///
+    /// Variable names are matched case-insensitively, so `%{http_host}`
+    /// expands like `%{HTTP_HOST}`. Only the prefix of names such as
+    /// `ENV:name` or `HTTP:Header-Name` is normalized, and sub-contexts
+    /// are always queried with uppercase names.
     #[inline]
     pub fn fill(&mut self, expr: &str) -> &str {
-        self.0
-            .iter_mut()
-            .find_map(|ctx| ctx.fill(expr))
-            .unwrap_or("")
+        let expr = &*normalize(expr);
+        if let Some(header) = header_name(expr)
+            && !self.vary.iter().any(|h| h.eq_ignore_ascii_case(&header))
+        {
+            self.vary.push(header);
+        }
+        if matches!(expr, "REQUEST_FILENAME" | "THE_REQUEST") && self.provided(expr).is_none() {
+            return match expr {
+                "REQUEST_FILENAME" => self.request_filename(),
+                _ => self.the_request(),
+            };
+        }
+        if let Some((prefix, name)) = expr.split_once(':')
+            && prefix.eq_ignore_ascii_case("env")
+            && let Some(value) = self.env.get(name)
+        {
+            return value;
+        }
+        if let Some(name) = expr.strip_prefix("QUERY:") {
+            return self
+                .query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+                .unwrap_or("");
+        }
+        if let Some(name) = expr.strip_prefix("QUERY_ALL:") {
+            return self.query_all.get(name).map(|v| v.as_str()).unwrap_or("");
+        }
+        if let Some(index) = expr.strip_prefix("PATH_SEGMENT:") {
+            return index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| self.segments.get(i.checked_sub(1)?))
+                .map(|s| s.as_str())
+                .unwrap_or("");
+        }
+        let missing = &mut self.missing;
+        match self.providers.iter_mut().find_map(|ctx| ctx.fill(expr)) {
+            Some(value) => value,
+            None => match CONSTANTS.iter().find(|(key, _)| *key == expr) {
+                Some((_, value)) => value,
+                None => {
+                    *missing = true;
+                    ""
+                }
+            },
+        }
+    }
+
+    /// Resolve `REQUEST_FILENAME` by joining the decoded uri path onto the
+    /// `DOCUMENT_ROOT` supplied by a sub-context such as [`ServerCtx`].
+    ///
+    /// Expands to an empty string without a document root, so file-tests
+    /// against it never match rather than failing.
+    fn request_filename(&mut self) -> &str {
+        self.derived.clear();
+        let root = self
+            .provided("DOCUMENT_ROOT")
+            .filter(|root| !root.is_empty())
+            .map(|root| root.trim_end_matches('/').to_owned());
+        if let Some(root) = root {
+            let path = percent_decode_str(&self.path).decode_utf8_lossy();
+            self.derived.push_str(&root);
+            self.derived.push('/');
+            self.derived.push_str(path.trim_start_matches('/'));
+        }
+        &self.derived
+    }
+
+    /// Compose `THE_REQUEST` from the `REQUEST_METHOD`, `REQUEST_URI`,
+    /// `QUERY_STRING` and `SERVER_PROTOCOL` supplied by sub-contexts.
+    ///
+    /// Expands to an empty string without both a method and uri, and
+    /// omits the protocol when it is not supplied.
+    fn the_request(&mut self) -> &str {
+        self.derived.clear();
+        let method = self.provided("REQUEST_METHOD").map(str::to_owned);
+        let uri = self.provided("REQUEST_URI").map(str::to_owned);
+        let (Some(method), Some(uri)) = (method, uri) else {
+            return &self.derived;
+        };
+        let query = self.provided("QUERY_STRING").unwrap_or_default().to_owned();
+        let protocol = self
+            .provided("SERVER_PROTOCOL")
+            .unwrap_or_default()
+            .to_owned();
+        self.derived = format!("{method} {}", extra::join_query(uri, &query));
+        if !protocol.is_empty() {
+            self.derived.push(' ');
+            self.derived.push_str(&protocol);
+        }
+        &self.derived
+    }
+
+    /// Value of the variable supplied by the first sub-context, if any.
+    #[inline]
+    fn provided(&mut self, key: &str) -> Option<&str> {
+        self.providers.iter_mut().find_map(|ctx| ctx.fill(key))
+    }
+
+    /// Returns true if every variable referenced within the expression is
+    /// supplied, even if its value is empty.
+    ///
+    /// Distinguishes absent request headers from present but empty ones,
+    /// which both expand to an empty string.
+    pub(crate) fn is_present(&mut self, expr: &str) -> bool {
+        variables(expr).all(|name| self.has_variable(name))
+    }
+
+    fn has_variable(&mut self, name: &str) -> bool {
+        if let Some(key) = name.strip_prefix("QUERY:") {
+            return self.query.iter().any(|(k, _)| k == key);
+        }
+        if let Some(key) = name.strip_prefix("QUERY_ALL:") {
+            return self.query_all.contains_key(key);
+        }
+        let missing = std::mem::take(&mut self.missing);
+        let present = !self.fill(name).is_empty() || !self.missing;
+        self.missing |= missing;
+        present
+    }
+
+    /// Number of request header names recorded so far, used as a mark
+    /// for [`EngineCtx::truncate_vary`].
+    #[inline]
+    pub(crate) fn vary_len(&self) -> usize {
+        self.vary.len()
+    }
+
+    /// Forget the request header names recorded after the mark.
+    #[inline]
+    pub(crate) fn truncate_vary(&mut self, mark: usize) {
+        self.vary.truncate(mark);
+    }
+
+    /// Take the request header names recorded since the last reset.
+    #[inline]
+    pub(crate) fn take_vary(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.vary)
+    }
+
+    /// Environment variables assigned by `[E=VAR:VAL]` rule flags since
+    /// the start of the last [`Engine::rewrite_ctx`](crate::Engine::rewrite_ctx) call.
+    ///
+    /// Also returned by [`Outcome::env`](crate::Outcome::env).
+    #[inline]
+    pub fn env_vars(&self) -> &HashMap<String, String> {
+        &self.env
+    }
+
+    /// Assign the uri currently being rewritten.
+    ///
+    /// Supplies `REQUEST_FILENAME` unless provided by a sub-context, and
+    /// the decoded `PATH_SEGMENT:N` variables, where `N`
+    /// is the 1-based index of a non-empty path segment, and the decoded
+    /// `QUERY:name` variables holding the first value of each query parameter.
+    ///
+    /// `QUERY_ALL:name` variables hold every value of a repeated parameter
+    /// joined by commas in the order they appear, so `?tag=a&tag=b` expands
+    /// to `a,b`. Commas within decoded values are not escaped.
+    pub(crate) fn set_uri(&mut self, uri: &str) {
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+        self.query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .map(|(key, value)| (decode_query(key), decode_query(value)))
+            .collect();
+        self.query_all.clear();
+        for (key, value) in self.query.iter() {
+            match self.query_all.get_mut(key) {
+                Some(values) => {
+                    values.push(',');
+                    values.push_str(value);
+                }
+                None => {
+                    self.query_all.insert(key.to_owned(), value.to_owned());
+                }
+            }
+        }
+        self.path = path.to_owned();
+        self.segments = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+            .collect();
+    }
+
+    /// Assign or unset an environment variable set by a `[E=...]` rule flag
+    /// for the remainder of the rewrite.
+    ///
+    /// Assigned variables take priority over any sub-context for
+    /// `%{ENV:VAR}` expansions.
+    pub(crate) fn set_env(&mut self, name: &str, value: Option<&str>) {
+        match value {
+            Some(value) => self.env.insert(name.to_owned(), value.to_owned()),
+            None => self.env.remove(name),
+        };
+    }
+
+    /// Assign the captures of the last matched regex condition used
+    /// to expand `%N` back-references.
+    #[inline]
+    pub(crate) fn set_captures(&mut self, captures: Vec<String>) {
+        self.captures = captures;
+    }
+
+    /// Clear all state tracked during a single rewrite.
+    #[inline]
+    pub(crate) fn reset(&mut self) {
+        self.missing = false;
+        self.vary.clear();
+        self.file_tests.clear();
+        self.captures.clear();
+        self.env.clear();
+    }
+
+    /// Assign the [`TextMap`](crate::TextMap) instances registered
+    /// with the engine for `${name:key}` lookups, if any.
+    #[inline]
+    pub(crate) fn set_maps(&mut self, maps: Option<Arc<Maps>>) {
+        self.maps = maps;
+    }
+
+    /// Returns true if a map is registered under the name.
+    #[inline]
+    pub(crate) fn has_map(&self, name: &str) -> bool {
+        self.maps
+            .as_ref()
+            .is_some_and(|maps| maps.contains_key(name))
+    }
+
+    /// Lookup a `key|default` argument within the named map, expanding
+    /// to the default (or an empty string) when the key is missing.
+    pub(crate) fn lookup(&self, name: &str, arg: &str) -> String {
+        let (key, default) = arg.split_once('|').unwrap_or((arg, ""));
+        self.maps
+            .as_ref()
+            .and_then(|maps| maps.get(name))
+            .and_then(|map| map.get(key))
+            .unwrap_or_else(|| default.to_owned())
+    }
+
+    /// Return true if any variable was missing from every sub-context
+    /// since the last call, and reset the tracker.
+    #[inline]
+    pub(crate) fn take_missing(&mut self) -> bool {
+        std::mem::take(&mut self.missing)
     }
 
     /// Replace all variables within expression with data
     /// specified within with the [`EngineCtx`] and return
     /// the updated string.
-    pub fn replace_all(&mut self, expr: &str) -> String {
-        MATCHER
-            .find_iter(expr)
-            .map(|c| expr[c.range()].to_owned())
-            .fold(expr.to_owned(), |acc, key| {
-                let attr = key.trim_matches(|c| ['%', '{', '}'].contains(&c));
-                acc.replace(&key, self.fill(attr))
-            })
+    ///
+    /// Expressions without any variables are returned borrowed
+    /// without allocating.
+    pub fn replace_all<'s>(&mut self, expr: &'s str) -> Cow<'s, str> {
+        self.replace(expr, &MATCHER)
+    }
+
+    /// Replace all variables like [`EngineCtx::replace_all`] along with
+    /// `%N` back-references (`%0` to `%9`) to the captures of the last
+    /// matched regex condition within the current group.
+    ///
+    /// Both are expanded in a single pass so expanded values are never
+    /// re-interpreted, and unset captures expand to an empty string.
+    pub(crate) fn expand<'s>(&mut self, expr: &'s str) -> Cow<'s, str> {
+        self.replace(expr, &BACKREF_MATCHER)
+    }
+
+    /// Expand the `%{VARIABLE}` and `%N` references within the expression
+    /// like [`EngineCtx::expand`] into `dst`, handing the text between
+    /// them to `text`.
+    ///
+    /// Expanded values are never passed to `text`, so they are never
+    /// re-interpreted by it.
+    pub(crate) fn expand_into(
+        &mut self,
+        expr: &str,
+        mut text: impl FnMut(&str, &mut String),
+        dst: &mut String,
+    ) {
+        let mut last = 0;
+        for m in BACKREF_MATCHER.find_iter(expr) {
+            text(&expr[last..m.start()], dst);
+            self.push_reference(&expr[m.range()], dst);
+            last = m.end();
+        }
+        text(&expr[last..], dst);
+    }
+
+    fn replace<'s>(&mut self, expr: &'s str, matcher: &Regex) -> Cow<'s, str> {
+        let mut matches = matcher.find_iter(expr).peekable();
+        if matches.peek().is_none() {
+            return Cow::Borrowed(expr);
+        }
+        let mut result = String::with_capacity(expr.len());
+        let mut last = 0;
+        for m in matches {
+            result.push_str(&expr[last..m.start()]);
+            self.push_reference(&expr[m.range()], &mut result);
+            last = m.end();
+        }
+        result.push_str(&expr[last..]);
+        Cow::Owned(result)
+    }
+
+    /// Push the value of a single `%{VARIABLE}` or `%N` reference.
+    fn push_reference(&mut self, reference: &str, dst: &mut String) {
+        match reference.len() {
+            2 => {
+                let index = reference[1..].parse::<usize>().unwrap_or(0);
+                if let Some(capture) = self.captures.get(index) {
+                    dst.push_str(capture);
+                }
+            }
+            _ => dst.push_str(self.fill(&reference[2..reference.len() - 1])),
+        }
+    }
+}
+
+/// Uppercase a variable name, or only its prefix for names such as
+/// `ENV:name` whose suffix is case-sensitive.
+fn normalize(key: &str) -> Cow<'_, str> {
+    let name = key.split_once(':').map_or(key, |(prefix, _)| prefix);
+    match name.bytes().any(|b| b.is_ascii_lowercase()) {
+        true => Cow::Owned(name.to_ascii_uppercase() + &key[name.len()..]),
+        false => Cow::Borrowed(key),
+    }
+}
+
+/// Retrieve the request header name associated with a header variable.
+///
+/// Supports both `HTTP:Header-Name` and `HTTP_HEADER_NAME` forms.
+fn header_name(key: &str) -> Option<String> {
+    if let Some((prefix, name)) = key.split_once(':') {
+        return prefix.eq_ignore_ascii_case("http").then(|| name.to_owned());
     }
+    let name = key.strip_prefix("HTTP_")?;
+    let words: Vec<String> = name
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase().to_string() + &chars.as_str().to_lowercase())
+                .unwrap_or_default()
+        })
+        .collect();
+    Some(words.join("-"))
 }
 
 /// Environment Variable Context.
@@ -111,30 +620,56 @@ impl ContextProvider for EnvCtx {
 }
 
 /// All variables and references associated with `TIME_` prefix.
+///
+/// Variables are formatted like Apache, where `TIME_WDAY` is the numeric
+/// day of the week starting with `0` for Sunday and `TIME` is formatted
+/// as `YYYYMMDDHHMMSS`.
+///
+/// The non-standard `TIME_MONTH` and `TIME_WDAY_NAME` variables provide
+/// the same month as `TIME_MON` and the full name of the weekday.
 #[derive(Clone, Debug)]
 pub struct DateCtx {
     time_year: String,
-    time_month: String,
+    time_mon: String,
     time_day: String,
     time_hour: String,
     time_min: String,
     time_sec: String,
     time_wday: String,
+    time_wday_name: String,
     time: String,
 }
 
 impl DateCtx {
+    /// Build [`DateCtx`] from the current local time.
+    #[inline]
     pub fn new() -> Self {
-        let date = chrono::Local::now();
+        Self::from_datetime(&chrono::Local::now())
+    }
+
+    /// Build [`DateCtx`] from the current time in UTC.
+    #[inline]
+    pub fn utc() -> Self {
+        Self::from_datetime(&chrono::Utc::now())
+    }
+
+    /// Build [`DateCtx`] from a fixed date and time, expanding
+    /// the variables in its timezone.
+    pub fn from_datetime<Tz>(date: &chrono::DateTime<Tz>) -> Self
+    where
+        Tz: chrono::TimeZone,
+        Tz::Offset: fmt::Display,
+    {
         Self {
             time_year: date.format("%Y").to_string(),
-            time_month: date.format("%m").to_string(),
+            time_mon: date.format("%m").to_string(),
             time_day: date.format("%d").to_string(),
             time_hour: date.format("%H").to_string(),
             time_min: date.format("%M").to_string(),
             time_sec: date.format("%S").to_string(),
-            time_wday: date.format("%A").to_string(),
-            time: date.format("%Y-%m-%d %H:%M:%S").to_string(),
+            time_wday: date.format("%w").to_string(),
+            time_wday_name: date.format("%A").to_string(),
+            time: date.format("%Y%m%d%H%M%S").to_string(),
         }
     }
 }
@@ -149,12 +684,13 @@ impl ContextProvider for DateCtx {
     fn fill(&mut self, key: &str) -> Option<&str> {
         match key {
             "TIME_YEAR" => Some(self.time_year.as_str()),
-            "TIME_MONTH" => Some(self.time_month.as_str()),
+            "TIME_MON" | "TIME_MONTH" => Some(self.time_mon.as_str()),
             "TIME_DAY" => Some(self.time_day.as_str()),
             "TIME_HOUR" => Some(self.time_hour.as_str()),
             "TIME_MIN" => Some(self.time_min.as_str()),
             "TIME_SEC" => Some(self.time_sec.as_str()),
             "TIME_WDAY" => Some(self.time_wday.as_str()),
+            "TIME_WDAY_NAME" => Some(self.time_wday_name.as_str()),
             "TIME" => Some(self.time.as_str()),
             _ => None,
         }
@@ -193,6 +729,12 @@ impl ServerCtx {
         Ok(self)
     }
 
+    /// Assign value for `SERVER_PORT` variable
+    pub fn server_port(mut self, server_port: u16) -> Self {
+        self.server_port = Some(server_port.to_string());
+        self
+    }
+
     /// Assign value for `SERVER_ADDR`, and `SERVER_PORT` variables if address is Some.
     pub fn maybe_server_addr<A: ToSocketAddrs>(self, server_addr: Option<A>) -> io::Result<Self> {
         match server_addr {
@@ -217,11 +759,40 @@ impl ContextProvider for ServerCtx {
     }
 }
 
+/// Decode a percent-encoded query component, treating `+` as a space.
+fn decode_query(s: &str) -> String {
+    percent_decode_str(&s.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Strip any trailing port from a host, preserving bracketed IPv6 addresses.
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port))
+            if port.chars().all(|c| c.is_ascii_digit())
+                && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host,
+    }
+}
+
 /// All variables and references associated with `REMOTE_` prefix
 /// and other request variables.
+///
+/// `HTTP_HOST` includes the port exactly as sent by the client like Apache,
+/// while the non-standard `HTTP_HOST_NOPORT` variable provides it stripped.
+///
+/// `HTTPS` expands to `on` when `REQUEST_SCHEME` is `https` and to `off`
+/// otherwise, including when no scheme is assigned.
 #[derive(Clone, Debug, Default)]
 pub struct RequestCtx {
     auth_type: Option<String>,
+    content_length: Option<String>,
+    content_type: Option<String>,
+    http_host: Option<String>,
     ipv6: Option<String>,
     path_info: Option<String>,
     query_string: Option<String>,
@@ -229,29 +800,113 @@ pub struct RequestCtx {
     remote_host: Option<String>,
     remote_port: Option<String>,
     request_method: Option<String>,
+    request_scheme: Option<String>,
     request_uri: Option<String>,
+    the_request: Option<String>,
+    headers: Vec<(String, String)>,
 }
 
 impl RequestCtx {
     setter!(auth_type, AUTH_TYPE);
+    setter!(content_type, CONTENT_TYPE);
+    setter!(http_host, HTTP_HOST);
     setter!(ipv6, IPV6);
     setter!(path_info, PATH_INFO);
     setter!(query_string, QUERY_STRING);
     setter!(request_method, REQUEST_METHOD);
+    setter!(request_scheme, REQUEST_SCHEME);
     setter!(request_uri, REQUEST_URI);
+    setter!(the_request, THE_REQUEST);
 
-    /// Assign value for `REMOTE_ADDR`, `REMOTE_HOST`, and `REMOTE_PORT` variables.
+    /// Assign value for `REMOTE_ADDR`, `REMOTE_HOST`, and `REMOTE_PORT` variables,
+    /// along with `IPV6` as `on` or `off` depending on the address family.
+    ///
+    /// `REMOTE_HOST` is assigned the ip address, see
+    /// [`RequestCtx::resolve_remote_host`] to assign a hostname instead.
     pub fn remote_addr<A: ToSocketAddrs>(mut self, remote_addr: A) -> io::Result<Self> {
         let addr = remote_addr
             .to_socket_addrs()?
             .next()
             .expect("missing socket address");
+        self.ipv6 = Some(if addr.is_ipv6() { "on" } else { "off" }.to_owned());
         self.remote_addr = Some(addr.to_string());
         self.remote_host = Some(addr.ip().to_string());
         self.remote_port = Some(addr.port().to_string());
         Ok(self)
     }
 
+    /// Assign value for the `HTTP:name` variable of a request header.
+    ///
+    /// Header names are matched case-insensitively, and headers assigned
+    /// with an empty value are still considered present by `-present`.
+    ///
+    /// Headers also supply the fixed `HTTP_*` variables such as
+    /// `HTTP_USER_AGENT` or `HTTP_REFERER`, where underscores stand for
+    /// dashes in the header name.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Assign `REQUEST_SCHEME` to `https` or `http` depending on whether
+    /// the request was made over TLS.
+    pub fn https(self, https: bool) -> Self {
+        self.request_scheme(if https { "https" } else { "http" })
+    }
+
+    /// Find the value of a request header by its case-insensitive name.
+    fn find_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Assign value for `CONTENT_LENGTH` variable
+    pub fn content_length(mut self, content_length: u64) -> Self {
+        self.content_length = Some(content_length.to_string());
+        self
+    }
+
+    /// Assign value for `REMOTE_HOST` variable, replacing the ip address
+    /// assigned by [`RequestCtx::remote_addr`].
+    pub fn remote_host<S: Into<String>>(mut self, remote_host: S) -> Self {
+        self.remote_host = Some(remote_host.into());
+        self
+    }
+
+    /// Assign `REMOTE_HOST` to the hostname of the remote address found
+    /// by the [`HostResolver`], keeping the ip address when it is unknown.
+    ///
+    /// The engine never performs DNS lookups itself, so `REMOTE_HOST`
+    /// expands to the ip address unless this method or
+    /// [`RequestCtx::remote_host`] is called.
+    pub fn resolve_remote_host(self, resolver: &impl HostResolver) -> Self {
+        let name = self
+            .remote_addr
+            .as_deref()
+            .and_then(|addr| addr.parse::<SocketAddr>().ok())
+            .and_then(|addr| resolver.resolve(addr.ip()));
+        match name {
+            Some(name) => self.remote_host(name),
+            None => self,
+        }
+    }
+
+    /// Assign value for `REMOTE_PORT` variable
+    pub fn remote_port(mut self, remote_port: u16) -> Self {
+        self.remote_port = Some(remote_port.to_string());
+        self
+    }
+
+    /// Assign value for `REQUEST_SCHEME` variable if scheme is Some.
+    pub fn maybe_request_scheme(self, scheme: Option<&str>) -> Self {
+        match scheme {
+            Some(scheme) => self.request_scheme(scheme),
+            None => self,
+        }
+    }
+
     /// Assign value for `REMOTE_ADDR`, `REMOTE_HOST`, and `REMOTE_PORT`
     /// variables if address is Some.
     pub fn maybe_remote_addr<A: ToSocketAddrs>(self, remote_addr: Option<A>) -> io::Result<Self> {
@@ -262,10 +917,89 @@ impl RequestCtx {
     }
 }
 
+#[cfg(feature = "http")]
+impl RequestCtx {
+    /// Build [`RequestCtx`] from the parts of an [`http::Request`].
+    ///
+    /// Assigns `REQUEST_METHOD`, `REQUEST_URI`, `QUERY_STRING`,
+    /// `THE_REQUEST`, `HTTP_HOST`, `CONTENT_LENGTH` and `CONTENT_TYPE`
+    /// variables, along with `HTTP:name` for every request header.
+    pub fn from_http(parts: &http::request::Parts) -> Self {
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        let the_request = format!("{} {path} {:?}", parts.method, parts.version);
+        let host = parts
+            .headers
+            .get(http::header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| parts.uri.authority().map(|a| a.as_str()));
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.trim().to_owned())
+        };
+        Self {
+            content_length: header(http::header::CONTENT_LENGTH)
+                .filter(|len| len.parse::<u64>().is_ok()),
+            content_type: header(http::header::CONTENT_TYPE),
+            http_host: host.map(|h| h.to_owned()),
+            headers: parts
+                .headers
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str().to_owned(), v.to_str().ok()?.to_owned())))
+                .collect(),
+            ..Default::default()
+        }
+        .request_method(parts.method.as_str())
+        .maybe_request_scheme(parts.uri.scheme_str())
+        .request_uri(parts.uri.path())
+        .query_string(parts.uri.query().unwrap_or_default())
+        .the_request(the_request)
+    }
+}
+
+#[cfg(feature = "http")]
+impl EngineCtx<'_> {
+    /// Build [`EngineCtx`] for an [`http::Request`] from its parts along
+    /// with the [`ServerCtx`] and [`DateCtx`] of the server handling it.
+    ///
+    /// Request variables are assigned by [`RequestCtx::from_http`], along
+    /// with `REMOTE_ADDR`, `REMOTE_HOST` and `REMOTE_PORT` when the request
+    /// carries a [`SocketAddr`](std::net::SocketAddr) extension.
+    pub fn from_http_request(
+        parts: &http::request::Parts,
+        server: ServerCtx,
+        date: DateCtx,
+    ) -> Self {
+        let request = RequestCtx::from_http(parts);
+        let request = match parts.extensions.get::<std::net::SocketAddr>() {
+            Some(addr) => request
+                .remote_addr(*addr)
+                .expect("socket addresses always resolve"),
+            None => request,
+        };
+        Self::default()
+            .with_ctx(request)
+            .with_ctx(server)
+            .with_ctx(date)
+    }
+}
+
 impl ContextProvider for RequestCtx {
     fn fill(&mut self, key: &str) -> Option<&str> {
         match key {
             "AUTH_TYPE" => get!(self.auth_type),
+            "CONTENT_LENGTH" => get!(self.content_length),
+            "CONTENT_TYPE" => get!(self.content_type),
+            "HTTP_HOST" => get!(self.http_host).or_else(|| self.find_header("Host")),
+            "HTTP_HOST_NOPORT" => get!(self.http_host)
+                .or_else(|| self.find_header("Host"))
+                .map(strip_port),
             "IPV6" => get!(self.ipv6),
             "PATH_INFO" => get!(self.path_info),
             "QUERY_STRING" => get!(self.query_string),
@@ -273,8 +1007,23 @@ impl ContextProvider for RequestCtx {
             "REMOTE_HOST" => get!(self.remote_host),
             "REMOTE_PORT" => get!(self.remote_port),
             "REQUEST_METHOD" => get!(self.request_method),
+            "REQUEST_SCHEME" => get!(self.request_scheme),
+            "HTTPS" => match self.request_scheme.as_deref() {
+                Some(scheme) if scheme.eq_ignore_ascii_case("https") => Some("on"),
+                _ => Some("off"),
+            },
             "REQUEST_URI" => get!(self.request_uri),
-            _ => None,
+            "THE_REQUEST" => get!(self.the_request),
+            _ => match key.strip_prefix("HTTP_") {
+                Some(name) => self.find_header(&name.replace('_', "-")),
+                None => {
+                    let (prefix, name) = key.split_once(':')?;
+                    if !prefix.eq_ignore_ascii_case("http") {
+                        return None;
+                    }
+                    self.find_header(name)
+                }
+            },
         }
     }
 }
@@ -6,15 +6,27 @@ pub enum CondError {
     #[error("Invalid string pattern expression")]
     InvalidPattern(String),
 
+    #[error("Invalid regex in condition pattern")]
+    InvalidRegex(String),
+
     #[error("Invalid comparison expression")]
     InvalidComparison(String),
 
+    #[error("Comparison operand is not an integer")]
+    NotAnInteger(String),
+
+    #[error("Invalid CIDR network in ipmatch expression")]
+    InvalidCidr(String),
+
     #[error("Invalid filetest expression")]
     InvalidFileTest(String),
 
     #[error("Quotation never closed in expression")]
     UnclosedQuotation(String),
 
+    #[error("Unsupported expr condition, only `true` and `false` are supported")]
+    UnsupportedExpr(String),
+
     #[error("Rule condition expression is empty")]
     EmptyExpression,
 
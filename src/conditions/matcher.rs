@@ -1,12 +1,17 @@
+use std::cmp::Ordering;
+use std::fmt;
 use std::iter::Peekable;
+use std::net::IpAddr;
 use std::ops::Deref;
-use std::{os::unix::fs::PermissionsExt, path::PathBuf, str::FromStr};
+use std::str::FromStr;
 
+use regex_automata::{meta::Regex, util};
 use unicase::UniCase;
 
-use super::context::EngineCtx;
+use super::context::{EngineCtx, FileInfo};
 use super::error::CondError;
 use super::parse::*;
+use crate::map;
 
 /// Abstraction for String value that supports toggling case
 /// insensitivity when evaluating [`Match`] comparisons
@@ -29,67 +34,125 @@ impl Deref for Value {
 impl Value {
     /// Build new [`Value`] instance with the configured settings.
     ///
-    /// Replaces all variables using [`EngineCtx`] before configuring
-    /// for case-sensitive settings.
+    /// Replaces all variables and `%N` back-references using [`EngineCtx`]
+    /// and evaluates any `${function:argument}` map calls and `${name:key}`
+    /// map lookups before configuring for case-sensitive settings.
     pub fn new(s: &str, nocase: bool, ctx: &mut EngineCtx) -> Self {
-        let value = ctx.replace_all(s);
+        let value = ctx.expand(s);
+        let value = map::expand(&value, ctx);
         match nocase {
             true => Self::NoCase(UniCase::new(value)),
             false => Self::Case(value),
         }
     }
 
-    /// [`String::starts_with`](std::string::String) abstraction.
-    pub fn starts_with(&self, s: &Value) -> bool {
+    /// Lexicographical [`Ord::cmp`] abstraction.
+    pub fn compare(&self, s: &Value) -> Ordering {
         match self {
-            Self::Case(c) => c.starts_with(s.deref()),
-            Self::NoCase(c) => c.starts_with(s.deref()),
+            Self::Case(c) => c.as_str().cmp(s.as_str()),
+            Self::NoCase(c) => UniCase::new(c.as_str()).cmp(&UniCase::new(s.as_str())),
         }
     }
 
-    /// [`String::starts_with`](std::string::String) abstraction.
-    pub fn ends_with(&self, s: &Value) -> bool {
-        match self {
-            Self::Case(c) => c.ends_with(s.deref()),
-            Self::NoCase(c) => c.ends_with(s.deref()),
-        }
+    /// Check if value equals any item in the comma-separated list.
+    pub fn is_member(&self, list: &Value) -> bool {
+        list.split(',')
+            .map(|item| item.trim())
+            .any(|item| match self {
+                Self::Case(c) => c == item,
+                Self::NoCase(c) => *c == UniCase::new(item),
+            })
     }
 }
 
 /// Compiled condition logical expression.
 ///
-/// Supports `CondPattern`, integer comparisons, and file attribute tests
-/// with negated variations.
+/// Supports `CondPattern`, regular expressions, integer comparisons,
+/// list membership, and file attribute tests with negated variations.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Match {
     Pattern(String, Pattern, String),
     NotPattern(String, Pattern, String),
+    Regex(String, CondRegex),
+    NotRegex(String, CondRegex),
     Compare(String, Compare, String),
+    Member(String, String),
+    NotMember(String, String),
     FileTest(String, FileTest),
     NotFileTest(String, FileTest),
+    /// Non-Apache `-present` test that every variable within the test
+    /// string is supplied, even if empty.
+    Present(String),
+    NotPresent(String),
+    Network(String, Cidr),
+    NotNetwork(String, Cidr),
+    /// Constant `expr true` or `expr false` condition.
+    Const(bool),
 }
 
 impl Match {
-    pub(crate) fn parse<I>(tokens: &mut Peekable<I>) -> Result<Self, CondError>
+    pub(crate) fn parse<I>(tokens: &mut Peekable<I>, nocase: bool) -> Result<Self, CondError>
     where
         I: Iterator<Item = String>,
     {
         let first = tokens.next().ok_or(CondError::EmptyExpression)?;
         let expr = tokens.next().ok_or(CondError::MissingComparison)?;
 
+        if first.eq_ignore_ascii_case("expr") {
+            return match expr.to_lowercase().as_str() {
+                "true" => Ok(Self::Const(true)),
+                "false" => Ok(Self::Const(false)),
+                _ => Err(CondError::UnsupportedExpr(expr)),
+            };
+        }
+
         let not = expr.starts_with('!');
         let expr = expr.trim_start_matches('!');
         if let Some(c) = matches_start(expr, &['<', '>', '=']) {
-            let (_, second) = expr
-                .split_once(c)
-                .ok_or(CondError::InvalidPattern(expr.to_owned()))?;
-            let pattern = Pattern::from_str(&expr[..expr.len() - second.len()])?;
+            let len = match c != '=' && expr[1..].starts_with('=') {
+                true => 2,
+                false => 1,
+            };
+            let (pattern, second) = expr.split_at(len);
+            let pattern = Pattern::from_str(pattern)?;
             return match not {
                 true => Ok(Self::NotPattern(first, pattern, second.to_owned())),
                 false => Ok(Self::Pattern(first, pattern, second.to_owned())),
             };
         }
 
+        if expr == "-in" {
+            let second = tokens.next().ok_or(CondError::MissingSuffix)?;
+            return match not {
+                true => Ok(Self::NotMember(first, second)),
+                false => Ok(Self::Member(first, second)),
+            };
+        }
+
+        if !expr.starts_with('-') {
+            let regex = CondRegex::new(expr, nocase)?;
+            return match not {
+                true => Ok(Self::NotRegex(first, regex)),
+                false => Ok(Self::Regex(first, regex)),
+            };
+        }
+
+        if expr == "-ipmatch" {
+            let cidr = tokens.next().ok_or(CondError::MissingSuffix)?;
+            let cidr = Cidr::from_str(&cidr)?;
+            return match not {
+                true => Ok(Self::NotNetwork(first, cidr)),
+                false => Ok(Self::Network(first, cidr)),
+            };
+        }
+
+        if expr == "-present" {
+            return match not {
+                true => Ok(Self::NotPresent(first)),
+                false => Ok(Self::Present(first)),
+            };
+        }
+
         let second = tokens.peek();
         if second.is_some_and(|s| !s.starts_with('[')) {
             let second = tokens.next().unwrap();
@@ -97,6 +160,9 @@ impl Match {
                 return Err(CondError::InvalidComparison(expr.to_owned()));
             }
             let cmp = Compare::from_str(expr)?;
+            if !second.contains(['%', '$']) && second.trim().parse::<i32>().is_err() {
+                return Err(CondError::NotAnInteger(second));
+            }
             return Ok(Self::Compare(first, cmp, second.to_owned()));
         }
 
@@ -108,12 +174,122 @@ impl Match {
     }
 }
 
+impl Match {
+    /// Heap memory used by any compiled regular expression in bytes.
+    pub(crate) fn memory_usage(&self) -> usize {
+        match self {
+            Self::Regex(_, re) | Self::NotRegex(_, re) => re.memory_usage(),
+            _ => 0,
+        }
+    }
+
+    /// Retrieve the expressions of the match expanded at evaluation,
+    /// starting with the test string.
+    pub(crate) fn expressions(&self) -> Vec<&str> {
+        match self {
+            Self::Pattern(v1, _, v2)
+            | Self::NotPattern(v1, _, v2)
+            | Self::Compare(v1, _, v2)
+            | Self::Member(v1, v2)
+            | Self::NotMember(v1, v2) => vec![v1, v2],
+            Self::Regex(v1, _)
+            | Self::NotRegex(v1, _)
+            | Self::FileTest(v1, _)
+            | Self::NotFileTest(v1, _)
+            | Self::Present(v1)
+            | Self::NotPresent(v1)
+            | Self::Network(v1, _)
+            | Self::NotNetwork(v1, _) => vec![v1],
+            Self::Const(_) => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pattern(v1, pt, v2) => write!(f, "{} {}", quote(v1), quote(&format!("{pt}{v2}"))),
+            Self::NotPattern(v1, pt, v2) => {
+                write!(f, "{} {}", quote(v1), quote(&format!("!{pt}{v2}")))
+            }
+            Self::Regex(v1, re) => write!(f, "{} {}", quote(v1), quote(&re.source)),
+            Self::NotRegex(v1, re) => {
+                write!(f, "{} {}", quote(v1), quote(&format!("!{}", re.source)))
+            }
+            Self::Compare(v1, cp, v2) => write!(f, "{} {cp} {}", quote(v1), quote(v2)),
+            Self::Member(v1, v2) => write!(f, "{} -in {}", quote(v1), quote(v2)),
+            Self::NotMember(v1, v2) => write!(f, "{} !-in {}", quote(v1), quote(v2)),
+            Self::FileTest(v1, ft) => write!(f, "{} {ft}", quote(v1)),
+            Self::NotFileTest(v1, ft) => write!(f, "{} !{ft}", quote(v1)),
+            Self::Present(v1) => write!(f, "{} -present", quote(v1)),
+            Self::NotPresent(v1) => write!(f, "{} !-present", quote(v1)),
+            Self::Network(v1, cidr) => write!(f, "{} -ipmatch {cidr}", quote(v1)),
+            Self::NotNetwork(v1, cidr) => write!(f, "{} !-ipmatch {cidr}", quote(v1)),
+            Self::Const(value) => write!(f, "expr {value}"),
+        }
+    }
+}
+
 impl FromStr for Match {
     type Err = CondError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut tokens = tokenize(s)?.into_iter().peekable();
-        Self::parse(&mut tokens)
+        let mut tokens = tokenize(s, CondError::UnclosedQuotation)?
+            .into_iter()
+            .peekable();
+        Self::parse(&mut tokens, false)
+    }
+}
+
+/// Compiled regular expression `CondPattern`.
+#[derive(Clone, Debug)]
+pub struct CondRegex {
+    source: String,
+    regex: Regex,
+}
+
+impl CondRegex {
+    /// Compile the regular expression with the configured case sensitivity.
+    pub fn new(source: &str, nocase: bool) -> Result<Self, CondError> {
+        let regex = Regex::builder()
+            .syntax(util::syntax::Config::new().case_insensitive(nocase))
+            .build(source)
+            .map_err(|err| CondError::InvalidRegex(err.to_string()))?;
+        Ok(Self {
+            source: source.to_owned(),
+            regex,
+        })
+    }
+
+    /// Heap memory used by the compiled regular expression in bytes.
+    #[inline]
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.regex.memory_usage()
+    }
+
+    /// Check if the regular expression matches anywhere within the value,
+    /// returning all capture groups (unmatched groups are empty).
+    pub fn captures(&self, value: Value) -> Option<Vec<String>> {
+        let mut caps = self.regex.create_captures();
+        self.regex.captures(value.as_str(), &mut caps);
+        if !caps.is_match() {
+            return None;
+        }
+        let captures = (0..caps.group_len())
+            .map(|i| {
+                caps.get_group(i)
+                    .map(|span| &value[span.range()])
+                    .unwrap_or("")
+            })
+            .map(|s| s.to_owned())
+            .collect();
+        Some(captures)
+    }
+}
+
+impl PartialEq for CondRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
     }
 }
 
@@ -128,16 +304,32 @@ pub enum Pattern {
 }
 
 impl Pattern {
-    /// Evaluate `CondPattern` according to defintion.
+    /// Evaluate `CondPattern` according to defintion, comparing
+    /// both values lexicographically.
     pub fn matches(&self, first: Value, second: Value) -> bool {
+        let ordering = first.compare(&second);
         match self {
-            Self::Preceeds | Self::PreceedsOrEquals => first.starts_with(&second),
-            Self::Follows | Self::FollowsOrEquals => first.ends_with(&second),
-            Self::Equals => first == second,
+            Self::Preceeds => ordering.is_lt(),
+            Self::Follows => ordering.is_gt(),
+            Self::Equals => ordering.is_eq(),
+            Self::PreceedsOrEquals => ordering.is_le(),
+            Self::FollowsOrEquals => ordering.is_ge(),
         }
     }
 }
 
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Preceeds => "<",
+            Self::Follows => ">",
+            Self::Equals => "=",
+            Self::PreceedsOrEquals => "<=",
+            Self::FollowsOrEquals => ">=",
+        })
+    }
+}
+
 impl FromStr for Pattern {
     type Err = CondError;
 
@@ -166,11 +358,15 @@ pub enum Compare {
 
 impl Compare {
     /// Evaluate integer expression according to definition.
+    ///
+    /// Surrounding whitespace is ignored, and values that are not
+    /// integers never match, including variables expanding to an empty
+    /// string.
     pub fn compare(&self, first: Value, second: Value) -> bool {
-        let Some(first) = first.parse::<i32>().ok() else {
+        let Some(first) = first.trim().parse::<i32>().ok() else {
             return false;
         };
-        let Some(second) = second.parse::<i32>().ok() else {
+        let Some(second) = second.trim().parse::<i32>().ok() else {
             return false;
         };
         match self {
@@ -184,6 +380,19 @@ impl Compare {
     }
 }
 
+impl fmt::Display for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "-eq",
+            Self::GreaterThan => "-gt",
+            Self::GreaterOrEqual => "-ge",
+            Self::LesserThan => "-lt",
+            Self::LesserOrEqual => "-le",
+            Self::NotEqual => "-ne",
+        })
+    }
+}
+
 impl FromStr for Compare {
     type Err = CondError;
 
@@ -200,8 +409,68 @@ impl FromStr for Compare {
     }
 }
 
-/// File attribute-test expression definition.
+/// IPv4 or IPv6 network in CIDR notation used by `-ipmatch`.
+///
+/// Addresses without a prefix length match only themselves.
 #[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Check if the address is within the network.
+    ///
+    /// Values that are not valid addresses, or belong to the other
+    /// address family, never match.
+    pub fn contains(&self, value: &str) -> bool {
+        let Ok(addr) = value.trim().parse::<IpAddr>() else {
+            return false;
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CondError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CondError::InvalidCidr(s.to_owned());
+        let (addr, prefix) = s.split_once('/').map_or((s, None), |(a, p)| (a, Some(p)));
+        let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => bits,
+        };
+        if prefix > bits {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// File attribute-test expression definition.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum FileTest {
     Dir,
     File,
@@ -211,24 +480,34 @@ pub enum FileTest {
 }
 
 impl FileTest {
-    /// Evaluate file attribute-test according to defintion.
-    pub fn matches(&self, path: Value) -> bool {
-        let path = PathBuf::from(path.deref());
+    /// Evaluate file attribute-test according to defintion against
+    /// the resolved file attributes, if the file exists.
+    pub fn matches(&self, info: Option<&FileInfo>) -> bool {
+        let Some(info) = info else {
+            return false;
+        };
         match self {
-            Self::Dir => path.is_dir(),
-            Self::File => path.is_file(),
-            Self::Symbolic => path.is_symlink(),
-            Self::SizedFile => {
-                path.is_file() && path.metadata().map(|m| m.len() > 0).unwrap_or(false)
-            }
-            Self::Executable => path
-                .metadata()
-                .map(|m| m.permissions().mode() & 0o111 != 0)
-                .unwrap_or(false),
+            Self::Dir => info.is_dir,
+            Self::File => info.is_file,
+            Self::Symbolic => info.is_symlink,
+            Self::SizedFile => info.is_file && info.size > 0,
+            Self::Executable => info.executable,
         }
     }
 }
 
+impl fmt::Display for FileTest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Dir => "-d",
+            Self::File => "-f",
+            Self::Symbolic => "-h",
+            Self::SizedFile => "-s",
+            Self::Executable => "-x",
+        })
+    }
+}
+
 impl FromStr for FileTest {
     type Err = CondError;
 
@@ -274,6 +553,33 @@ mod tests {
             Match::from_str(r#"%{REMOTE_PORT} -wtf 4000 "#).err(),
             Some(CondError::InvalidComparison(_))
         ));
+        assert_eq!(
+            Match::from_str(r#"%{REMOTE_PORT} -lt http "#).err(),
+            Some(CondError::NotAnInteger("http".to_owned()))
+        );
+        assert!(Match::from_str(r#"%{REMOTE_PORT} -lt %{SERVER_PORT} "#).is_ok());
+    }
+
+    #[test]
+    fn test_member() {
+        assert_eq!(
+            Match::from_str(r#" %{REQUEST_METHOD} -in GET,HEAD "#).ok(),
+            Some(Match::Member(
+                String::from("%{REQUEST_METHOD}"),
+                String::from("GET,HEAD"),
+            ))
+        );
+        assert_eq!(
+            Match::from_str(r#" %{REQUEST_METHOD} !-in GET,HEAD "#).ok(),
+            Some(Match::NotMember(
+                String::from("%{REQUEST_METHOD}"),
+                String::from("GET,HEAD"),
+            ))
+        );
+        assert_eq!(
+            Match::from_str(r#" %{REQUEST_METHOD} -in "#).err(),
+            Some(CondError::MissingSuffix)
+        );
     }
 
     #[test]
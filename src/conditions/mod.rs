@@ -1,12 +1,14 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 pub mod context;
 mod error;
 mod matcher;
-mod parse;
+pub(crate) mod parse;
 
 use matcher::{Match, Value};
 
+use super::extra::ParseMode;
+
 pub use context::EngineCtx;
 pub use error::CondError;
 
@@ -17,13 +19,39 @@ pub use error::CondError;
 ///
 /// Supports a subset of [offical](https://httpd.apache.org/docs/current/mod/mod_rewrite.html#rewritecond)
 /// mod_rewrite rules.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Condition {
     matcher: Match,
     flags: Vec<CondFlag>,
+    unknown: Vec<String>,
 }
 
 impl Condition {
+    /// Parse a condition expression, skipping unknown flags rather than
+    /// failing when `mode` is [`ParseMode::Lenient`].
+    pub fn parse(s: &str, mode: ParseMode) -> Result<Self, CondError> {
+        let mut tokens = parse::tokenize(s, CondError::UnclosedQuotation)?;
+        let (flags, unknown) =
+            match tokens.len() > 2 && tokens.last().is_some_and(|t| t.starts_with('[')) {
+                true => parse_flags(&tokens.pop().unwrap_or_default(), mode)?,
+                false => (Vec::new(), Vec::new()),
+            };
+        let nocase = flags.iter().any(|f| matches!(f, CondFlag::NoCase));
+        let matcher = Match::parse(&mut tokens.into_iter().peekable(), nocase)?;
+        Ok(Self {
+            matcher,
+            flags,
+            unknown,
+        })
+    }
+
+    /// Retrieve the unknown flags skipped while parsing in
+    /// [`ParseMode::Lenient`].
+    #[inline]
+    pub fn unknown_flags(&self) -> &[String] {
+        &self.unknown
+    }
+
     /// Evaluate if the rewrite condition and return boolean result.
     pub fn is_met(&self, ctx: &mut EngineCtx) -> bool {
         let nocase = self.flags.iter().any(|f| matches!(f, CondFlag::NoCase));
@@ -34,14 +62,54 @@ impl Condition {
             Match::NotPattern(v1, pt, v2) => {
                 !pt.matches(Value::new(v1, nocase, ctx), Value::new(v2, nocase, ctx))
             }
+            Match::Regex(v1, re) => match re.captures(Value::new(v1, nocase, ctx)) {
+                Some(captures) => {
+                    ctx.set_captures(captures);
+                    true
+                }
+                None => false,
+            },
+            Match::NotRegex(v1, re) => re.captures(Value::new(v1, nocase, ctx)).is_none(),
             Match::Compare(v1, cp, v2) => {
                 cp.compare(Value::new(v1, nocase, ctx), Value::new(v2, nocase, ctx))
             }
-            Match::FileTest(v1, ft) => ft.matches(Value::new(v1, nocase, ctx)),
-            Match::NotFileTest(v1, ft) => !ft.matches(Value::new(v1, nocase, ctx)),
+            Match::Member(v1, v2) => {
+                Value::new(v1, nocase, ctx).is_member(&Value::new(v2, nocase, ctx))
+            }
+            Match::NotMember(v1, v2) => {
+                !Value::new(v1, nocase, ctx).is_member(&Value::new(v2, nocase, ctx))
+            }
+            Match::FileTest(v1, ft) => {
+                let path = Value::new(v1, nocase, ctx);
+                ctx.file_test(ft, &path)
+            }
+            Match::NotFileTest(v1, ft) => {
+                let path = Value::new(v1, nocase, ctx);
+                !ctx.file_test(ft, &path)
+            }
+            Match::Network(v1, cidr) => cidr.contains(&Value::new(v1, nocase, ctx)),
+            Match::NotNetwork(v1, cidr) => !cidr.contains(&Value::new(v1, nocase, ctx)),
+            Match::Const(value) => *value,
+            Match::Present(v1) => ctx.is_present(v1),
+            Match::NotPresent(v1) => !ctx.is_present(v1),
         }
     }
 
+    /// Iterate over the names of all `%{VARIABLE}` references expanded
+    /// when evaluating the condition.
+    pub(crate) fn variables(&self) -> impl Iterator<Item = &str> {
+        self.matcher
+            .expressions()
+            .into_iter()
+            .flat_map(context::variables)
+    }
+
+    /// Heap memory used by the compiled condition pattern in bytes.
+    #[inline]
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.matcher.memory_usage()
+    }
+
     /// Returns true if the rewrite condition uses OR operator rather
     /// than the default AND.
     #[inline]
@@ -50,50 +118,85 @@ impl Condition {
     }
 }
 
-impl FromStr for Condition {
-    type Err = CondError;
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.matcher)?;
+        if !self.flags.is_empty() {
+            let flags: Vec<_> = self.flags.iter().map(|f| f.to_string()).collect();
+            write!(f, " [{}]", flags.join(","))?;
+        }
+        Ok(())
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut tokens = parse::tokenize(s)?.into_iter().peekable();
-        let matcher = Match::parse(&mut tokens)?;
-        let flags = match tokens.next() {
-            Some(flags) => CondFlagList::from_str(&flags)?.0,
-            None => Vec::new(),
-        };
-        Ok(Self { matcher, flags })
+/// Conditions are serialized as their expression text and parsed again
+/// when deserialized.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Condition {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
-struct CondFlagList(Vec<CondFlag>);
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Condition {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
 
-impl FromStr for CondFlagList {
+impl FromStr for Condition {
     type Err = CondError;
 
+    #[inline]
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if !s.starts_with('[') || !s.ends_with(']') {
-            return Err(CondError::FlagsMissingBrackets(s.to_owned()));
-        }
-        let flags = s[1..s.len() - 1]
-            .split(',')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .map(CondFlag::from_str)
-            .collect::<Result<Vec<CondFlag>, _>>()?;
-        if flags.is_empty() {
-            return Err(CondError::FlagsEmpty);
+        Self::parse(s, ParseMode::Strict)
+    }
+}
+
+/// Parse a bracketed flag list into the known flags along with the
+/// unknown flags skipped in [`ParseMode::Lenient`].
+fn parse_flags(s: &str, mode: ParseMode) -> Result<(Vec<CondFlag>, Vec<String>), CondError> {
+    if !s.starts_with('[') || !s.ends_with(']') {
+        return Err(CondError::FlagsMissingBrackets(s.to_owned()));
+    }
+    let mut flags = Vec::new();
+    let mut unknown = Vec::new();
+    for flag in s[1..s.len() - 1]
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+    {
+        match CondFlag::from_str(flag) {
+            Ok(flag) => flags.push(flag),
+            Err(CondError::InvalidFlag(flag)) if mode == ParseMode::Lenient => unknown.push(flag),
+            Err(err) => return Err(err),
         }
-        Ok(Self(flags))
     }
+    if flags.is_empty() && unknown.is_empty() {
+        return Err(CondError::FlagsEmpty);
+    }
+    Ok((flags, unknown))
 }
 
 /// Supported `mod_rewrite` [`Condition`] flags that modify
 /// the conditions behavior.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 enum CondFlag {
     NoCase,
     Or,
 }
 
+impl fmt::Display for CondFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoCase => "NC",
+            Self::Or => "OR",
+        })
+    }
+}
+
 impl FromStr for CondFlag {
     type Err = CondError;
 
@@ -110,9 +213,22 @@ impl FromStr for CondFlag {
 mod tests {
     use super::*;
 
-    use context::{RequestCtx, ServerCtx};
+    use std::borrow::Cow;
+
+    use context::{DateCtx, RequestCtx, ServerCtx};
     use matcher::{Compare, FileTest, Pattern};
 
+    #[test]
+    fn test_unknown_flags() {
+        let cond = Condition::parse("%{HTTP_HOST} =a [OR,NV]", ParseMode::Lenient).unwrap();
+        assert!(cond.is_or());
+        assert_eq!(cond.unknown_flags(), ["NV"]);
+        assert_eq!(
+            Condition::from_str("%{HTTP_HOST} =a [OR,NV]"),
+            Err(CondError::InvalidFlag("NV".to_owned()))
+        );
+    }
+
     #[test]
     fn test_pattern() {
         let s1 = String::from("%{REQUEST_URI}");
@@ -123,7 +239,7 @@ mod tests {
             Match::Pattern(v1, Pattern::Equals, v2) if v1 == &s1 && v2 == &s2,
         ));
         assert_eq!(cond.flags.len(), 2);
-        assert!(matches!(cond.flags.get(0), Some(CondFlag::NoCase)));
+        assert!(matches!(cond.flags.first(), Some(CondFlag::NoCase)));
 
         let mut req = RequestCtx::default().request_uri("/Test");
         let mut ctx = EngineCtx::default().with_ctx(req);
@@ -154,6 +270,451 @@ mod tests {
         assert!(!cond.is_met(&mut ctx));
     }
 
+    #[test]
+    fn test_compare_port() {
+        let srv = ServerCtx::default().server_addr("[::1]:0443").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(srv);
+        assert_eq!(ctx.fill("SERVER_PORT"), "443");
+
+        let cond = Condition::from_str("%{SERVER_PORT} -eq 443").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(ServerCtx::default().server_port(443));
+        assert!(cond.is_met(&mut ctx));
+
+        let cond = Condition::from_str("%{REMOTE_PORT} -lt 1024").unwrap();
+        let req = RequestCtx::default()
+            .remote_addr("127.0.0.1:50000")
+            .unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("REMOTE_PORT"), "50000");
+        assert!(!cond.is_met(&mut ctx));
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().remote_port(80));
+        assert!(cond.is_met(&mut ctx));
+
+        let cond = Condition::from_str(r#"%{SERVER_PORT} -eq " 0443 ""#).unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(ServerCtx::default().server_port(443));
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_bucket() {
+        let cond = Condition::from_str("${bucket:%{REMOTE_HOST}:100} -lt 100").unwrap();
+        let req = RequestCtx::default().remote_addr("10.0.0.1:80").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+
+        let cond = Condition::from_str("${bucket:%{REMOTE_HOST}:100} -eq 37").unwrap();
+        for _ in 0..5 {
+            assert!(cond.is_met(&mut ctx));
+        }
+        let req = RequestCtx::default().remote_addr("10.0.0.2:80").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(!cond.is_met(&mut ctx));
+        let cond = Condition::from_str("${bucket:%{REMOTE_HOST}:100} -eq 4").unwrap();
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_display() {
+        for expr in [
+            r#"%{REQUEST_URI} =/Test [NC,OR]"#,
+            r#"%{HTTP_USER_AGENT} "!=Some Agent""#,
+            r#"%{CONTENT_TYPE} !^multipart/ [NC]"#,
+            r#"%{SERVER_PORT} -ge 4000"#,
+            r#"%{REQUEST_METHOD} !-in GET,HEAD"#,
+            r#"%{REQUEST_FILENAME} !-f"#,
+            r#"%{HTTP_USER_AGENT} "=say \"hi\" now""#,
+        ] {
+            let cond = Condition::from_str(expr).unwrap();
+            assert_eq!(cond.to_string(), expr);
+            assert_eq!(Condition::from_str(&cond.to_string()).unwrap(), cond);
+        }
+    }
+
+    #[test]
+    fn test_member() {
+        let cond = Condition::from_str("%{REQUEST_METHOD} -in GET,HEAD,OPTIONS [NC]").unwrap();
+
+        let req = RequestCtx::default().request_method("head");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+
+        let req = RequestCtx::default().request_method("POST");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(!cond.is_met(&mut ctx));
+
+        let cond = Condition::from_str("%{REQUEST_METHOD} !-in GET,HEAD,OPTIONS").unwrap();
+        let req = RequestCtx::default().request_method("head");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_path_segment() {
+        let mut ctx = EngineCtx::default();
+        ctx.set_uri("/a/b%20c/d?x=1");
+        assert_eq!(ctx.fill("PATH_SEGMENT:1"), "a");
+        assert_eq!(ctx.fill("PATH_SEGMENT:2"), "b c");
+        assert_eq!(ctx.fill("PATH_SEGMENT:3"), "d");
+        assert_eq!(ctx.fill("PATH_SEGMENT:4"), "");
+        assert_eq!(ctx.fill("PATH_SEGMENT:0"), "");
+        assert_eq!(ctx.replace_all("/%{PATH_SEGMENT:3}/"), "/d/");
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut ctx = EngineCtx::default();
+        assert_eq!(ctx.fill("IS_SUBREQ"), "false");
+        assert!(!ctx.take_missing());
+
+        let cond = Condition::from_str("%{IS_SUBREQ} =false").unwrap();
+        assert!(cond.is_met(&mut ctx));
+        let cond = Condition::from_str("%{IS_SUBREQ} ^false$").unwrap();
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        use chrono::{TimeZone, Utc};
+
+        let date = Utc.with_ymd_and_hms(2024, 2, 29, 23, 30, 5).unwrap();
+        let req = RequestCtx::default().http_host("example.com");
+        let mut ctx = EngineCtx::default()
+            .with_ctx(DateCtx::from_datetime(&date))
+            .with_ctx(req);
+        ctx.set_uri("/page?Key=v");
+        assert_eq!(
+            ctx.replace_all("%{time_year}"),
+            ctx.replace_all("%{TIME_YEAR}")
+        );
+        assert_eq!(ctx.fill("time_year"), "2024");
+        assert_eq!(ctx.fill("Http_Host"), "example.com");
+        assert_eq!(ctx.fill("is_subreq"), "false");
+        assert_eq!(ctx.fill("query:Key"), "v");
+        assert_eq!(ctx.fill("QUERY:key"), "");
+    }
+
+    #[test]
+    fn test_replace_all_cow() {
+        let req = RequestCtx::default().request_method("GET");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(matches!(
+            ctx.replace_all("/static/path"),
+            Cow::Borrowed("/static/path")
+        ));
+        assert!(matches!(
+            ctx.replace_all("%{REQUEST_METHOD}:%{REQUEST_METHOD}"),
+            Cow::Owned(s) if s == "GET:GET"
+        ));
+    }
+
+    #[test]
+    fn test_query_param() {
+        let mut ctx = EngineCtx::default();
+        ctx.set_uri("/page?utm_source=google&q=a%20b+c&utm_source=bing&flag");
+        assert_eq!(ctx.fill("QUERY:utm_source"), "google");
+        assert_eq!(ctx.fill("QUERY:q"), "a b c");
+        assert_eq!(ctx.fill("QUERY:flag"), "");
+        assert_eq!(ctx.fill("QUERY:missing"), "");
+
+        let cond = Condition::from_str("%{QUERY:utm_source} ^google$").unwrap();
+        assert!(cond.is_met(&mut ctx));
+        ctx.set_uri("/page?utm_source=bing");
+        assert!(!cond.is_met(&mut ctx));
+        ctx.set_uri("/page");
+        assert!(!cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_query_all() {
+        let mut ctx = EngineCtx::default();
+        ctx.set_uri("/search?tag=red&size=m&tag=blue&tag=");
+        assert_eq!(ctx.fill("QUERY_ALL:tag"), "red,blue,");
+        assert_eq!(ctx.fill("QUERY_ALL:size"), "m");
+        assert_eq!(ctx.fill("QUERY_ALL:missing"), "");
+
+        let cond = Condition::from_str("%{QUERY_ALL:tag} (^|,)blue(,|$)").unwrap();
+        assert!(cond.is_met(&mut ctx));
+        ctx.set_uri("/search?tag=red");
+        assert!(!cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_ipmatch() {
+        let v4 = Condition::from_str("%{REMOTE_HOST} -ipmatch 10.0.0.0/8").unwrap();
+        let v6 = Condition::from_str("%{REMOTE_HOST} !-ipmatch 2001:db8::/32").unwrap();
+        assert_eq!(v4.to_string(), "%{REMOTE_HOST} -ipmatch 10.0.0.0/8");
+        for (addr, in_v4, in_v6) in [
+            ("10.1.2.3:80", true, false),
+            ("192.168.0.1:80", false, false),
+            ("[2001:db8::1]:80", false, true),
+            ("[2001:db9::1]:80", false, false),
+        ] {
+            let req = RequestCtx::default().remote_addr(addr).unwrap();
+            let mut ctx = EngineCtx::default().with_ctx(req);
+            assert_eq!(v4.is_met(&mut ctx), in_v4, "{addr}");
+            assert_eq!(v6.is_met(&mut ctx), !in_v6, "{addr}");
+        }
+
+        let exact = Condition::from_str("%{REMOTE_HOST} -ipmatch 127.0.0.1").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default());
+        assert!(!exact.is_met(&mut ctx));
+
+        for cidr in ["10.0.0.0/33", "10.0.0/8", "::1/129", "host/8"] {
+            assert_eq!(
+                Condition::from_str(&format!("%{{REMOTE_HOST}} -ipmatch {cidr}")),
+                Err(CondError::InvalidCidr(cidr.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_present() {
+        let cond = Condition::from_str("%{HTTP:X-Forwarded-Proto} -present").unwrap();
+        let empty = Condition::from_str("%{HTTP:X-Forwarded-Proto} ^$").unwrap();
+        assert_eq!(cond.to_string(), "%{HTTP:X-Forwarded-Proto} -present");
+
+        let req = RequestCtx::default().header("x-forwarded-proto", "");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+        assert!(empty.is_met(&mut ctx));
+
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default());
+        assert!(!cond.is_met(&mut ctx));
+        assert!(empty.is_met(&mut ctx));
+
+        let cond = Condition::from_str("%{QUERY:debug} !-present").unwrap();
+        ctx.set_uri("/page?debug");
+        assert!(!cond.is_met(&mut ctx));
+        ctx.set_uri("/page");
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_headers() {
+        let req = RequestCtx::default()
+            .header("User-Agent", "curl/8.0")
+            .header("referer", "https://example.com/")
+            .header("X-Forwarded-Proto", "https")
+            .header("Host", "example.com");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("HTTP_USER_AGENT"), "curl/8.0");
+        assert_eq!(ctx.fill("HTTP_REFERER"), "https://example.com/");
+        assert_eq!(ctx.fill("HTTP_X_FORWARDED_PROTO"), "https");
+        assert_eq!(ctx.fill("HTTP:x-forwarded-proto"), "https");
+        assert_eq!(ctx.fill("HTTP_HOST"), "example.com");
+        assert_eq!(ctx.fill("HTTP_COOKIE"), "");
+        assert_eq!(ctx.fill("HTTP:Cookie"), "");
+
+        let cond = Condition::from_str("%{HTTP_USER_AGENT} ^curl/").unwrap();
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_the_request() {
+        let cond = Condition::from_str(r"%{THE_REQUEST} \?.*(%3C|<)script [NC]").unwrap();
+
+        let req = RequestCtx::default().the_request("GET /search?q=<script> HTTP/1.1");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+
+        let req = RequestCtx::default()
+            .request_method("GET")
+            .request_uri("/search")
+            .query_string("q=%3CSCRIPT%3E");
+        let srv = ServerCtx::default().server_protocol("HTTP/1.1");
+        let mut ctx = EngineCtx::default().with_ctx(req).with_ctx(srv);
+        assert_eq!(
+            ctx.fill("THE_REQUEST"),
+            "GET /search?q=%3CSCRIPT%3E HTTP/1.1"
+        );
+        assert!(cond.is_met(&mut ctx));
+
+        let req = RequestCtx::default()
+            .request_method("GET")
+            .request_uri("/script");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("THE_REQUEST"), "GET /script");
+        assert!(!cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_datetime() {
+        use chrono::{FixedOffset, TimeZone, Utc};
+
+        let date = Utc.with_ymd_and_hms(2024, 2, 29, 23, 30, 5).unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(DateCtx::from_datetime(&date));
+        assert_eq!(ctx.fill("TIME_YEAR"), "2024");
+        assert_eq!(ctx.fill("TIME_DAY"), "29");
+        assert_eq!(ctx.fill("TIME_HOUR"), "23");
+        assert_eq!(ctx.fill("TIME_MON"), "02");
+        assert_eq!(ctx.fill("TIME_WDAY"), "4");
+        assert_eq!(ctx.fill("TIME_WDAY_NAME"), "Thursday");
+        assert_eq!(ctx.fill("TIME"), "20240229233005");
+
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let date = date.with_timezone(&offset);
+        let mut ctx = EngineCtx::default().with_ctx(DateCtx::from_datetime(&date));
+        assert_eq!(ctx.fill("TIME_MONTH"), "03");
+        assert_eq!(ctx.fill("TIME_HOUR"), "01");
+        assert_eq!(ctx.fill("TIME_WDAY"), "5");
+
+        let cond = Condition::from_str("%{TIME_YEAR} -ge 2024").unwrap();
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let addr: std::net::SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        let mut ctx =
+            EngineCtx::default().with_ctx(RequestCtx::default().remote_addr(addr).unwrap());
+        assert_eq!(ctx.fill("IPV6"), "on");
+        assert_eq!(ctx.fill("REMOTE_HOST"), "2001:db8::1");
+
+        let req = RequestCtx::default().remote_addr("127.0.0.1:80").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("IPV6"), "off");
+
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().ipv6("on"));
+        assert!(Condition::from_str("%{IPV6} =on").unwrap().is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_remote_host() {
+        use std::net::IpAddr;
+
+        use context::HostResolver;
+
+        struct Cache;
+
+        impl HostResolver for Cache {
+            fn resolve(&self, ip: IpAddr) -> Option<String> {
+                (ip == IpAddr::from([10, 0, 0, 1])).then(|| "client.example.com".to_owned())
+            }
+        }
+
+        let req = RequestCtx::default().remote_addr("10.0.0.1:80").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(req.resolve_remote_host(&Cache));
+        assert_eq!(ctx.fill("REMOTE_HOST"), "client.example.com");
+        assert_eq!(ctx.fill("REMOTE_ADDR"), "10.0.0.1:80");
+
+        let req = RequestCtx::default().remote_addr("10.0.0.2:80").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(req.resolve_remote_host(&Cache));
+        assert_eq!(ctx.fill("REMOTE_HOST"), "10.0.0.2");
+
+        let req = RequestCtx::default().resolve_remote_host(&Cache);
+        let mut ctx = EngineCtx::default().with_ctx(req.remote_host("host.example.com"));
+        assert_eq!(ctx.fill("REMOTE_HOST"), "host.example.com");
+    }
+
+    #[test]
+    fn test_http_host() {
+        let req = RequestCtx::default().http_host("example.com:8443");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("HTTP_HOST"), "example.com:8443");
+        assert_eq!(ctx.fill("HTTP_HOST_NOPORT"), "example.com");
+
+        let req = RequestCtx::default().http_host("[::1]:8443");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("HTTP_HOST"), "[::1]:8443");
+        assert_eq!(ctx.fill("HTTP_HOST_NOPORT"), "[::1]");
+
+        let req = RequestCtx::default().header("Host", "example.com:8080");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("HTTP_HOST"), "example.com:8080");
+        assert_eq!(ctx.fill("HTTP_HOST_NOPORT"), "example.com");
+
+        let cond = Condition::from_str("%{HTTP_HOST} =example.com:8443").unwrap();
+        let req = RequestCtx::default().http_host("example.com:8443");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_from_http() {
+        let (parts, _) = http::Request::get("http://localhost/path?x=1")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let req = RequestCtx::from_http(&parts);
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("THE_REQUEST"), "GET /path?x=1 HTTP/1.1");
+        assert_eq!(ctx.fill("REQUEST_URI"), "/path");
+        assert_eq!(ctx.fill("QUERY_STRING"), "x=1");
+        assert_eq!(ctx.fill("HTTP_HOST"), "localhost");
+
+        let (parts, _) = http::Request::get("/path")
+            .header("Host", "example.com:8443")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let req = RequestCtx::from_http(&parts);
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("HTTP_HOST"), "example.com:8443");
+
+        let (parts, _) = http::Request::post("/upload")
+            .header("Content-Type", "multipart/form-data; boundary=x")
+            .header("Content-Length", "2097152")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let req = RequestCtx::from_http(&parts);
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(ctx.fill("CONTENT_LENGTH"), "2097152");
+        assert!(ctx.fill("CONTENT_TYPE").starts_with("multipart/"));
+
+        let addr: std::net::SocketAddr = "[::1]:4000".parse().unwrap();
+        let (parts, _) = http::Request::get("https://example.com/path")
+            .extension(addr)
+            .body(())
+            .unwrap()
+            .into_parts();
+        let server = ServerCtx::default().server_name("example.com");
+        let mut ctx = EngineCtx::from_http_request(&parts, server, DateCtx::utc());
+        assert_eq!(ctx.fill("HTTPS"), "on");
+        assert_eq!(ctx.fill("REMOTE_HOST"), "::1");
+        assert_eq!(ctx.fill("IPV6"), "on");
+        assert_eq!(ctx.fill("SERVER_NAME"), "example.com");
+        assert_eq!(ctx.fill("TIME_YEAR").len(), 4);
+    }
+
+    #[test]
+    fn test_regex() {
+        let cond = Condition::from_str("%{CONTENT_TYPE} ^multipart/ [NC]").unwrap();
+        assert!(matches!(&cond.matcher, Match::Regex(v1, _) if v1 == "%{CONTENT_TYPE}"));
+
+        let req = RequestCtx::default().content_type("Multipart/form-data");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+
+        let req = RequestCtx::default().content_type("application/json");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(!cond.is_met(&mut ctx));
+
+        let cond = Condition::from_str("%{CONTENT_TYPE} !^multipart/").unwrap();
+        let req = RequestCtx::default().content_type("Multipart/form-data");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+
+        assert!(matches!(
+            Condition::from_str("%{CONTENT_TYPE} ^(multipart"),
+            Err(CondError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn test_content_length() {
+        let cond = Condition::from_str("%{CONTENT_LENGTH} -gt 1048576").unwrap();
+
+        let req = RequestCtx::default().content_length(2 * 1048576);
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(cond.is_met(&mut ctx));
+
+        let req = RequestCtx::default().content_length(1024);
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(!cond.is_met(&mut ctx));
+    }
+
     #[test]
     fn test_filetest() {
         let s1 = String::from("%{REQUEST_URI}");
@@ -173,4 +734,65 @@ mod tests {
         let mut ctx = EngineCtx::default().with_ctx(req);
         assert!(cond.is_met(&mut ctx));
     }
+
+    #[test]
+    fn test_negated() {
+        let cond = Condition::from_str("%{REQUEST_URI} !^/foo").unwrap();
+        assert!(matches!(&cond.matcher, Match::NotRegex(..)));
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_uri("/bar"));
+        assert!(cond.is_met(&mut ctx));
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_uri("/foo"));
+        assert!(!cond.is_met(&mut ctx));
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_uri("/FOO"));
+        assert!(cond.is_met(&mut ctx));
+
+        let cond = Condition::from_str("%{REQUEST_URI} !^/foo [NC]").unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_uri("/FOO"));
+        assert!(!cond.is_met(&mut ctx));
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_uri("/bar"));
+        assert!(cond.is_met(&mut ctx));
+
+        let cond = Condition::from_str("%{REQUEST_URI} !-d").unwrap();
+        let current = std::env::current_dir().unwrap().join("src");
+        let req = RequestCtx::default().request_uri(current.to_str().unwrap());
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert!(!cond.is_met(&mut ctx));
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_uri("/invalid"));
+        assert!(cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_lexical() {
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_uri("/m"));
+        for (expr, met) in [
+            ("</n", true),
+            ("</m", false),
+            ("<=/m", true),
+            (">/l", true),
+            (">/m", false),
+            (">=/m", true),
+            ("=/m", true),
+            ("=m", false),
+            ("!</n", false),
+            ("\"</N\" [NC]", true),
+            ("</N", false),
+        ] {
+            let cond = Condition::from_str(&format!("%{{REQUEST_URI}} {expr}")).unwrap();
+            assert_eq!(cond.is_met(&mut ctx), met, "{expr}");
+        }
+        let cond = Condition::from_str("%{REQUEST_URI} <=/m").unwrap();
+        assert!(
+            matches!(&cond.matcher, Match::Pattern(_, Pattern::PreceedsOrEquals, v2) if v2 == "/m")
+        );
+    }
+
+    #[test]
+    fn test_compare_empty() {
+        let mut ctx = EngineCtx::default();
+        assert_eq!(ctx.fill("HTTP:X-Count"), "");
+        for op in ["-eq", "-ne", "-lt", "-le", "-gt", "-ge"] {
+            let cond = Condition::from_str(&format!("%{{HTTP:X-Count}} {op} 0")).unwrap();
+            assert!(!cond.is_met(&mut ctx), "{op}");
+        }
+    }
 }
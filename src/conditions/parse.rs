@@ -1,51 +1,53 @@
-use super::error::CondError;
+use std::borrow::Cow;
 
-pub(crate) fn end_quote(s: &str, index: usize, quote: char) -> Result<usize, CondError> {
-    let mut backslashes = 0;
-    for (i, c) in s.chars().skip(index).enumerate() {
-        if c == quote && backslashes % 2 == 0 {
-            return Ok(index + i);
-        }
-        if c == '\\' {
-            backslashes += 1;
+/// Split an expression into whitespace separated tokens.
+///
+/// Tokens starting with `"` or `'` extend up to the matching quote, where
+/// `\"` or `\'` inserts the quote itself. An unclosed quote is reported
+/// using `unclosed` with the remainder of the expression.
+pub(crate) fn tokenize<E>(s: &str, unclosed: impl Fn(String) -> E) -> Result<Vec<String>, E> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
             continue;
         }
-        backslashes = 0;
-    }
-    Err(CondError::UnclosedQuotation(s[index - 1..].to_owned()))
-}
-
-pub(crate) fn tokenize(s: &str) -> Result<Vec<String>, CondError> {
-    let mut expressions: Vec<String> = Vec::new();
-    let mut expression: Vec<char> = Vec::new();
-    let mut index = 0;
-    while index < s.len() - 1 {
-        for (i, c) in s.chars().enumerate().skip(index) {
-            index = i;
-            if c.is_whitespace() && expression.is_empty() {
-                continue;
-            }
-            if c.is_whitespace() {
-                expressions.push(expression.iter().collect());
-                expression.clear();
-                continue;
+        let mut token = String::new();
+        if c != '"' && c != '\'' {
+            token.push(c);
+            while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+                token.push(c);
             }
-            if c == '\'' || c == '"' {
-                index = end_quote(s, i + 1, c)?;
-                expressions.push(s[i + 1..index].to_owned());
-                index += 1;
-                break;
+            tokens.push(token);
+            continue;
+        }
+        let quote = c;
+        loop {
+            match chars.next() {
+                Some((_, '\\')) if chars.peek().is_some_and(|(_, c)| *c == quote) => {
+                    token.push(quote);
+                    chars.next();
+                }
+                Some((_, c)) if c == quote => break,
+                Some((_, c)) => token.push(c),
+                None => return Err(unclosed(s[start..].to_owned())),
             }
-            expression.push(c);
         }
+        tokens.push(token);
     }
-    if !expression.is_empty() {
-        expressions.push(expression.iter().collect());
-    }
-    Ok(expressions)
+    Ok(tokens)
 }
 
 #[inline]
 pub(crate) fn matches_start(s: &str, matches: &'static [char]) -> Option<char> {
     matches.iter().find(|c| s.starts_with(**c)).copied()
 }
+
+/// Wrap a token in quotes if it would otherwise be split or unquoted
+/// by [`tokenize`].
+pub(crate) fn quote(s: &str) -> Cow<'_, str> {
+    if !s.is_empty() && !s.contains(char::is_whitespace) && !s.starts_with(['"', '\'']) {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(format!("\"{}\"", s.replace('"', "\\\"")))
+}
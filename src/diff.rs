@@ -0,0 +1,225 @@
+//! Rule-level comparison between two [`Engine`](super::Engine) instances.
+//!
+//! Intended for reviewing changes to rewrite configurations, where
+//! each [`Change`] reports the text representation of the affected rule.
+
+use super::expr::{ExprGroup, Expression};
+
+/// Single rule-level change between two engines.
+///
+/// Group indexes refer to the new engine, except for [`Change::Removed`]
+/// which refers to the old one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// Rule only present in the new engine.
+    Added { group: usize, rule: String },
+    /// Rule only present in the old engine.
+    Removed { group: usize, rule: String },
+    /// Rule replaced at the same position under the same conditions.
+    Modified {
+        group: usize,
+        old: String,
+        new: String,
+    },
+}
+
+/// Structured difference between two engines produced by
+/// [`Engine::diff`](super::Engine::diff).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineDiff {
+    pub changes: Vec<Change>,
+}
+
+impl EngineDiff {
+    /// Returns true if both engines contain the same rules.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Rule flattened with the context of the group it belongs to.
+struct Entry {
+    group: usize,
+    enabled: bool,
+    scope: Option<String>,
+    base: Option<String>,
+    conditions: Vec<String>,
+    rule: String,
+}
+
+impl Entry {
+    /// Compare entries ignoring their position within the engine.
+    fn same(&self, other: &Self) -> bool {
+        self.same_group(other) && self.rule == other.rule
+    }
+
+    /// Compare the group context of both entries.
+    fn same_group(&self, other: &Self) -> bool {
+        self.enabled == other.enabled
+            && self.scope == other.scope
+            && self.base == other.base
+            && self.conditions == other.conditions
+    }
+}
+
+fn flatten(groups: &[ExprGroup]) -> Vec<Entry> {
+    groups
+        .iter()
+        .enumerate()
+        .flat_map(|(group, g)| {
+            let conditions: Vec<String> = g
+                .conditions()
+                .iter()
+                .map(|c| Expression::Condition(c.clone()).to_string())
+                .collect();
+            g.rules().iter().map(move |r| Entry {
+                group,
+                enabled: g.is_enabled(),
+                scope: g.scope().map(|s| s.to_owned()),
+                base: g.base().map(|s| s.to_owned()),
+                conditions: conditions.clone(),
+                rule: Expression::Rule(r.clone()).to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Index pairs of the longest common subsequence of both entry lists.
+fn common(old: &[Entry], new: &[Entry]) -> Vec<(usize, usize)> {
+    let mut table = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            table[i][j] = match old[i].same(&new[j]) {
+                true => table[i + 1][j + 1] + 1,
+                false => table[i + 1][j].max(table[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut pairs = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i].same(&new[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Report the unmatched entries between two common anchors, pairing
+/// removals and additions under the same conditions as modifications.
+fn report(removed: &[Entry], added: &[Entry], changes: &mut Vec<Change>) {
+    let mut removed = removed.iter().peekable();
+    let mut added = added.iter().peekable();
+    loop {
+        match (removed.peek(), added.peek()) {
+            (Some(old), Some(new)) if old.same_group(new) => {
+                changes.push(Change::Modified {
+                    group: new.group,
+                    old: old.rule.clone(),
+                    new: new.rule.clone(),
+                });
+                removed.next();
+                added.next();
+            }
+            (Some(old), _) => {
+                changes.push(Change::Removed {
+                    group: old.group,
+                    rule: old.rule.clone(),
+                });
+                removed.next();
+            }
+            (None, Some(new)) => {
+                changes.push(Change::Added {
+                    group: new.group,
+                    rule: new.rule.clone(),
+                });
+                added.next();
+            }
+            (None, None) => break,
+        }
+    }
+}
+
+/// Compute the rule-level [`EngineDiff`] between two ordered lists of groups.
+pub(crate) fn diff(old: &[ExprGroup], new: &[ExprGroup]) -> EngineDiff {
+    let old = flatten(old);
+    let new = flatten(new);
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (ci, cj) in common(&old, &new)
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        report(&old[i..ci], &new[j..cj], &mut changes);
+        i = ci + 1;
+        j = cj + 1;
+    }
+    EngineDiff { changes }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::Engine;
+
+    #[test]
+    fn test_diff() {
+        let old = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} =old.example.com
+            RewriteRule ^/(.*) https://example.com/$1 [R=301]
+
+            RewriteRule ^/a$ /b
+            RewriteRule ^/legacy$ - [G]
+            RewriteRule ^/c$ /d [L]
+        "#,
+        )
+        .unwrap();
+        let new = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} =old.example.com
+            RewriteRule ^/(.*) https://example.com/$1 [R=301]
+
+            RewriteRule ^/a$ /b
+            RewriteRule ^/c$ /d [L]
+            RewriteRule ^/e$ /f [END]
+        "#,
+        )
+        .unwrap();
+
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(
+            old.diff(&new).changes,
+            vec![
+                Change::Removed {
+                    group: 1,
+                    rule: "RewriteRule ^/legacy$ - [G]".to_owned()
+                },
+                Change::Added {
+                    group: 1,
+                    rule: "RewriteRule ^/e$ /f [END]".to_owned()
+                },
+            ]
+        );
+
+        let modified = Engine::from_str("RewriteRule ^/a$ /z").unwrap();
+        let original = Engine::from_str("RewriteRule ^/a$ /b").unwrap();
+        assert_eq!(
+            original.diff(&modified).changes,
+            vec![Change::Modified {
+                group: 0,
+                old: "RewriteRule ^/a$ /b".to_owned(),
+                new: "RewriteRule ^/a$ /z".to_owned(),
+            }]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::num::ParseIntError;
+use std::{io, num::ParseIntError};
 
 use thiserror::Error;
 
@@ -7,8 +7,15 @@ pub use super::conditions::CondError;
 /// Errors when running expression engine
 #[derive(Debug, Error)]
 pub enum EngineError {
-    #[error("Too many iterations on rule processing. Infintite loop")]
-    TooManyIterations,
+    #[error("Too many iterations on rule processing (limit {0}). Infintite loop")]
+    TooManyIterations(usize),
+
+    #[error("Rewrite exceeded the configured time budget")]
+    Timeout,
+
+    #[cfg(feature = "http")]
+    #[error("Rewritten uri is not a valid http uri")]
+    InvalidUri(#[from] http::uri::InvalidUri),
 }
 
 /// Errors when parsing all rewrite expressions
@@ -23,11 +30,33 @@ pub enum ExpressionError {
     #[error("Invalid state rule")]
     InvalidStateRule(String),
 
-    #[error("Error when parisng condition rule")]
+    #[error("Invalid front controller index {0:?}")]
+    InvalidFrontController(String),
+
+    #[error(transparent)]
     ConditionError(#[from] CondError),
 
-    #[error("Error when parisng rewrite rule")]
+    #[error(transparent)]
     RuleError(#[from] RuleError),
+
+    #[error("Error when reading rules file")]
+    Io(#[from] io::Error),
+
+    #[error("line {line}: {source}")]
+    AtLine {
+        line: usize,
+        source: Box<ExpressionError>,
+    },
+}
+
+impl ExpressionError {
+    /// Line number of the expression that failed to parse, if known.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::AtLine { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
 }
 
 /// Errors when parsing rewrite rules
@@ -39,7 +68,7 @@ pub enum RuleError {
     #[error("Invalid regex in rule rewrite pattern")]
     InvalidRegex(String),
 
-    #[error("Rule is missing a rewrite expression")]
+    #[error("Rule is missing a rewrite expression (expected `pattern rewrite [flags]`)")]
     MissingRewrite,
 
     #[error("Invalid suffix to rule expression")]
@@ -62,4 +91,7 @@ pub enum RuleError {
 
     #[error("Invalid status code in rule definition")]
     InvalidFlagStatus(String),
+
+    #[error("Quotation never closed in rule expression")]
+    UnclosedQuotation(String),
 }
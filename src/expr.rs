@@ -1,24 +1,125 @@
-use std::str::FromStr;
+use std::{borrow::Cow, collections::HashMap, fmt, str::FromStr, time::Instant};
+
+use regex_automata::{Input, PatternID, PatternSet, meta::Regex};
 
 use crate::extra;
 
 use super::conditions::{Condition, EngineCtx};
 use super::error::{EngineError, ExpressionError};
-use super::extra::State;
-use super::rule::{Rule, RuleResolve, RuleShift};
+use super::extra::{ParseMode, State};
+use super::rule::{self, Rule, RuleResolve, RuleShift};
+use super::trace::{Trace, TraceEvent};
 
 /// Rewrite result.
 ///
 /// Includes either the re-write uri, or the instant http-response.
-#[derive(Debug)]
+///
+/// Engines return [`Rewrite::EndUri`] rather than [`Rewrite::Uri`] when
+/// an `[END]` rule applied, so callers chaining engines or re-running one
+/// after an internal redirect know to stop. Callers only dispatching the
+/// uri can use [`Rewrite::uri`] to handle both alike.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Rewrite {
+    /// Rewritten uri that may still be re-evaluated, such as after an
+    /// internal redirect or when another engine is applied.
     Uri(String),
+    /// Final uri produced by an `[END]` rule. Consumers should stop
+    /// processing and never re-run this or any other engine on it.
     EndUri(String),
+    /// External redirect with the expanded substitution, including any
+    /// query string, to use as the `Location` header along with the
+    /// given `3xx` status code.
     Redirect(String, u16),
+    /// Instant http-response with the given status code.
     StatusCode(u16),
 }
 
 impl Rewrite {
+    /// Returns true if the rewritten uri is an absolute url pointing to an
+    /// external location rather than a server-relative path.
+    ///
+    /// Urls with a scheme (`https://host/path`) and network-path references
+    /// (`//host/path`) are absolute, while paths (`/path`) are relative and
+    /// may be dispatched internally. Status codes are never absolute.
+    pub fn is_absolute_url(&self) -> bool {
+        match self {
+            Self::Uri(uri) | Self::EndUri(uri) | Self::Redirect(uri, _) => {
+                extra::is_absolute_url(uri)
+            }
+            Self::StatusCode(_) => false,
+        }
+    }
+
+    /// Returns true if no further rewriting should be applied, either
+    /// because the uri came from an `[END]` rule or because the rewrite
+    /// is an immediate http-response.
+    #[inline]
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, Self::Uri(_))
+    }
+
+    /// Build the immediate http-response of a [`Rewrite::Redirect`] or
+    /// [`Rewrite::StatusCode`], such as `302 Found` with the `Location`
+    /// header set or `410 Gone`.
+    ///
+    /// Returns `None` for rewritten uris, which are dispatched rather than
+    /// answered. Invalid statuses respond with `500 Internal Server Error`.
+    #[cfg(feature = "http")]
+    pub fn into_response(self) -> Option<http::Response<()>> {
+        let (status, location) = match self {
+            Self::Uri(_) | Self::EndUri(_) => return None,
+            Self::Redirect(location, status) => (status, Some(location)),
+            Self::StatusCode(status) => (status, None),
+        };
+        let mut response = http::Response::new(());
+        *response.status_mut() =
+            http::StatusCode::from_u16(status).unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
+        if let Some(location) = location.and_then(|l| http::HeaderValue::try_from(l).ok()) {
+            response
+                .headers_mut()
+                .insert(http::header::LOCATION, location);
+        }
+        Some(response)
+    }
+
+    /// Retrieve the rewritten uri of a [`Rewrite::Uri`] or
+    /// [`Rewrite::EndUri`].
+    #[inline]
+    pub fn uri(&self) -> Option<&str> {
+        match self {
+            Self::Uri(uri) | Self::EndUri(uri) => Some(uri),
+            _ => None,
+        }
+    }
+
+    /// Retrieve the `Location` of an external redirect.
+    #[inline]
+    pub fn location(&self) -> Option<&str> {
+        match self {
+            Self::Redirect(uri, _) => Some(uri),
+            _ => None,
+        }
+    }
+
+    /// Reattach the scheme and authority stripped before evaluation to
+    /// uris that are not already absolute urls.
+    pub(crate) fn with_origin(self, origin: &str) -> Self {
+        if origin.is_empty() {
+            return self;
+        }
+        let join = |uri: String| match extra::is_absolute_url(&uri) {
+            true => uri,
+            false => format!("{origin}{uri}"),
+        };
+        match self {
+            Self::Uri(uri) => Self::Uri(join(uri)),
+            Self::EndUri(uri) => Self::EndUri(join(uri)),
+            Self::Redirect(uri, sc) => Self::Redirect(join(uri), sc),
+            Self::StatusCode(sc) => Self::StatusCode(sc),
+        }
+    }
+
     /// Pass query-string back into uri after rewrite evaluation
     pub(crate) fn with_query(self, query: &str) -> Self {
         match self {
@@ -30,16 +131,194 @@ impl Rewrite {
     }
 }
 
+/// Reason rule processing stopped, see [`Outcome::termination_reason`].
+///
+/// Proxying with `[P]` is not supported, so no reason exists for it yet.
+/// The enum is non-exhaustive so such reasons can be added later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TerminationReason {
+    /// A group was stopped by an `[L]` rule and no later rule
+    /// produced a final response.
+    Last,
+    /// An `[END]` rule stopped all processing.
+    End,
+    /// A rule produced an external redirect.
+    Redirect,
+    /// A rule, or a missing variable with fail-closed enabled, produced
+    /// an instant http-response.
+    Status,
+    /// Every group was evaluated without any terminal rule firing.
+    FellThrough,
+    /// Every group was evaluated without any terminal rule firing, and
+    /// a `[C]` rule failed to match, skipping the rest of its chain.
+    Chain,
+}
+
+/// [`Rewrite`] produced by [`Engine::rewrite_outcome`](crate::Engine::rewrite_outcome)
+/// along with the reason rule processing stopped and the number of
+/// rules applied.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    rewrite: Rewrite,
+    reason: TerminationReason,
+    applied: usize,
+    vary: Vec<String>,
+    env: HashMap<String, String>,
+}
+
+impl Outcome {
+    /// Build an [`Outcome`] from the rewrite, where `last` marks that
+    /// an `[L]` rule stopped a group.
+    pub(crate) fn new(rewrite: Rewrite, last: bool) -> Self {
+        let reason = match &rewrite {
+            Rewrite::Uri(_) if last => TerminationReason::Last,
+            Rewrite::Uri(_) => TerminationReason::FellThrough,
+            Rewrite::EndUri(_) => TerminationReason::End,
+            Rewrite::Redirect(_, _) => TerminationReason::Redirect,
+            Rewrite::StatusCode(_) => TerminationReason::Status,
+        };
+        Self {
+            rewrite,
+            reason,
+            applied: 0,
+            vary: Vec::new(),
+            env: HashMap::new(),
+        }
+    }
+
+    /// Report a [`TerminationReason::Chain`] rather than falling through
+    /// when a `[C]` chain was broken.
+    #[inline]
+    pub(crate) fn with_broken_chain(mut self, broken: bool) -> Self {
+        if broken && self.reason == TerminationReason::FellThrough {
+            self.reason = TerminationReason::Chain;
+        }
+        self
+    }
+
+    /// Record the request headers the outcome depends on.
+    #[inline]
+    pub(crate) fn with_vary(mut self, vary: Vec<String>) -> Self {
+        self.vary = vary;
+        self
+    }
+
+    /// Record the environment variables assigned while rewriting.
+    #[inline]
+    pub(crate) fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Record the number of rules applied to produce the outcome.
+    #[inline]
+    pub(crate) fn with_applied(mut self, applied: usize) -> Self {
+        self.applied = applied;
+        self
+    }
+
+    /// Resulting [`Rewrite`].
+    #[inline]
+    pub fn rewrite(&self) -> &Rewrite {
+        &self.rewrite
+    }
+
+    /// Reattach the scheme and authority stripped before evaluation,
+    /// see [`Rewrite::with_origin`].
+    pub(crate) fn with_origin(self, origin: &str) -> Self {
+        Self {
+            rewrite: self.rewrite.with_origin(origin),
+            ..self
+        }
+    }
+
+    /// Consume the outcome, returning only the [`Rewrite`].
+    #[inline]
+    pub fn into_rewrite(self) -> Rewrite {
+        self.rewrite
+    }
+
+    /// Reason rule processing stopped.
+    #[inline]
+    pub fn termination_reason(&self) -> TerminationReason {
+        self.reason
+    }
+
+    /// Number of rule applications performed, counting a rule again each
+    /// time it matched after `[N]` restarted its group.
+    #[inline]
+    pub fn rules_applied(&self) -> usize {
+        self.applied
+    }
+
+    /// Names of the request headers referenced by the conditions of every
+    /// group whose conditions were met, such as `User-Agent` for
+    /// `%{HTTP_USER_AGENT}` or `%{HTTP:User-Agent}`.
+    ///
+    /// Headers referenced by conditions of groups whose conditions were
+    /// not met, or by rule substitutions, are not included, except for the
+    /// group whose missing variable produced a fail-closed response.
+    /// Responses should list these in their `Vary` header to stay cacheable.
+    #[inline]
+    pub fn vary_headers(&self) -> &[String] {
+        &self.vary
+    }
+
+    /// Environment variables assigned by the `[E=VAR:VAL]` flags of every
+    /// applied rule, where later assignments replace earlier ones and
+    /// `[E=!VAR]` removes the variable.
+    #[inline]
+    pub fn env(&self) -> &HashMap<String, String> {
+        &self.env
+    }
+
+    /// Returns true if any rule matched and was applied, even if its
+    /// substitution left the uri unchanged.
+    ///
+    /// A default rewrite configured by
+    /// [`Engine::set_default_rewrite`](crate::Engine::set_default_rewrite)
+    /// is not a rule application.
+    #[inline]
+    pub fn is_modified(&self) -> bool {
+        self.applied > 0
+    }
+}
+
+/// Rule matched by [`Engine::match_only`](crate::Engine::match_only).
+///
+/// Identifies the matching rule by the index of its [`ExprGroup`] and its
+/// index within the group, along with the captures of its pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteMatch {
+    pub group: usize,
+    pub rule: usize,
+    /// Positional captures, starting with the full match at index 0.
+    pub captures: Vec<Option<String>>,
+    /// Named captures that participated in the match.
+    pub named: HashMap<String, String>,
+}
+
 /// Logical grouping of [`Expression`] instances.
 ///
 /// Associates a list [`Condition`] instances that guard
 /// rewrites defined by [`Rule`].
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "GroupRepr", from = "GroupRepr")
+)]
 pub struct ExprGroup {
     conditions: Vec<Condition>,
     rules: Vec<Rule>,
     enabled: bool,
+    scope: Option<String>,
+    base: Option<String>,
     max_iterations: usize,
+    method_preserving: bool,
+    /// Combined rule patterns used to find candidate rules in one search.
+    set: Option<Regex>,
 }
 
 impl ExprGroup {
@@ -52,88 +331,456 @@ impl ExprGroup {
         let mut conditions = Vec::new();
         let mut rules = Vec::new();
         let mut enabled = true;
+        let mut scope = None;
+        let mut base = None;
         for expr in expressions {
             match expr {
                 Expression::Condition(cond) => conditions.push(cond),
                 Expression::Rule(rule) => rules.push(rule),
                 Expression::State(state) => enabled = matches!(state, State::On),
+                Expression::Scope(prefix) => scope = Some(prefix),
+                Expression::Base(path) => base = Some(path),
+                Expression::Comment(_) | Expression::Blank => {}
             }
         }
         Self {
             conditions,
+            set: rule::compile_set(&rules),
             rules,
             enabled,
+            scope,
+            base,
             max_iterations: 10,
+            method_preserving: false,
         }
     }
 
     /// Configure max number of loops over entire ruleset during
     /// rewrite before error
     ///
+    /// Every search for the next matching rule counts as an iteration,
+    /// including the final one finding no match. A limit of `0` is raised
+    /// to `1` so a group without matching rules never fails.
+    ///
     /// Default is 10
     pub fn max_iterations(mut self, iterations: usize) -> Self {
-        self.max_iterations = iterations;
+        self.max_iterations = iterations.max(1);
+        self
+    }
+
+    /// Configure `[R=permanent]` and `[R=temp]` redirects to use the
+    /// method-preserving `308` and `307` statuses rather than `301` and `302`.
+    ///
+    /// Default is false
+    pub fn method_preserving_redirects(mut self, enabled: bool) -> Self {
+        self.method_preserving = enabled;
         self
     }
 
+    /// Returns true if the group is enabled by its `RewriteEngine` state.
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// [`Condition`] instances guarding the group.
+    #[inline]
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    /// [`Rule`] instances evaluated by the group.
+    #[inline]
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Uri path prefix the group is limited to by `RewriteScope`, if any.
+    #[inline]
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
+
+    /// Base path prepended to relative substitutions by `RewriteBase`, if any.
+    #[inline]
+    pub fn base(&self) -> Option<&str> {
+        self.base.as_deref()
+    }
+
+    /// Prepend the `RewriteBase` to a substitution that is neither
+    /// a path starting with `/` nor an absolute url.
+    fn with_base(&self, uri: String) -> String {
+        match &self.base {
+            Some(base) if !uri.starts_with('/') && !extra::is_absolute_url(&uri) => {
+                match base.ends_with('/') {
+                    true => format!("{base}{uri}"),
+                    false => format!("{base}/{uri}"),
+                }
+            }
+            _ => uri,
+        }
+    }
+
+    /// Indexes of the rules whose pattern matches the uri path, or `None`
+    /// when every rule must be tried in turn.
+    fn candidates(&self, path: &str) -> Option<PatternSet> {
+        let set = self.set.as_ref()?;
+        let mut matches = PatternSet::new(set.pattern_len());
+        set.which_overlapping_matches(&Input::new(path), &mut matches);
+        Some(matches)
+    }
+
+    /// Returns true if the uri path falls within the group's scope.
+    ///
+    /// Groups without a `RewriteScope` apply to every uri.
+    #[inline]
+    pub fn in_scope(&self, uri: &str) -> bool {
+        self.scope
+            .as_ref()
+            .is_none_or(|prefix| uri.starts_with(prefix.as_str()))
+    }
+
+    /// Returns true if the group is guarded by any [`Condition`].
+    #[inline]
+    pub fn has_conditions(&self) -> bool {
+        !self.conditions.is_empty()
+    }
+
     /// Check all relevant [`Condition`] expressions are met.
     ///
+    /// Conditions are evaluated in order, where `[OR]` binds a condition
+    /// to the following one like `mod_rewrite`, so `A [OR]`, `B`, `C`
+    /// evaluates as `(A || B) && C`. Conditions following a met `[OR]`
+    /// condition within the same chain are skipped.
+    ///
+    /// Captures of the last matched regex condition are available to the
+    /// following conditions as `%N` back-references.
+    ///
     /// This method guards [`ExprGroup::rewrite`].
     pub fn match_conditions(&self, ctx: &mut EngineCtx) -> bool {
         if !self.enabled {
             return false;
         }
-        let (or, and): (Vec<_>, Vec<_>) = self.conditions.iter().partition(|c| c.is_or());
-        or.into_iter().any(|c| c.is_met(ctx)) || and.into_iter().all(|c| c.is_met(ctx))
+        ctx.set_captures(Vec::new());
+        // set once any condition of the current `[OR]` chain is met
+        let mut satisfied = false;
+        for cond in self.conditions.iter() {
+            let met = satisfied || cond.is_met(ctx);
+            if cond.is_or() {
+                satisfied = met;
+                continue;
+            }
+            if !met {
+                return false;
+            }
+            satisfied = false;
+        }
+        satisfied || self.conditions.last().is_none_or(|c| !c.is_or())
     }
 
     /// Evaluate the given URI against the configured [`Rule`] definitions
     /// and generate a [`Rewrite`] response.
+    ///
+    /// Rules match the uri path only. Like `mod_rewrite`, a substitution
+    /// containing a query replaces the current query string unless the
+    /// rule uses `[QSA]`, in which case the current query is appended.
+    /// Substitutions without a query keep the current query string,
+    /// including one produced by an earlier rule.
+    #[inline]
     pub fn rewrite(&self, uri: &str) -> Result<Rewrite, EngineError> {
+        self.rewrite_ctx(uri, &mut EngineCtx::default(), None)
+            .map(Outcome::into_rewrite)
+    }
+
+    /// Returns true if any rule pattern matches the path of the uri,
+    /// allowing callers to skip the group without allocating.
+    ///
+    /// Fails with [`EngineError::Timeout`] once the deadline is reached
+    /// between rules.
+    pub(crate) fn may_match(
+        &self,
+        uri: &str,
+        deadline: Option<Instant>,
+    ) -> Result<bool, EngineError> {
+        let path = uri.split_once('?').map_or(uri, |(path, _)| path);
+        if let Some(set) = &self.set {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(EngineError::Timeout);
+            }
+            return Ok(set.is_match(path));
+        }
+        for rule in self.rules.iter() {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(EngineError::Timeout);
+            }
+            if rule.is_match(path) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Evaluate the given URI like [`ExprGroup::rewrite`] using the
+    /// [`EngineCtx`] for variables expanded in substitutions and
+    /// environment variables assigned by matched rules.
+    ///
+    /// Fails with [`EngineError::Timeout`] once the deadline is reached
+    /// between rules.
+    #[inline]
+    pub(crate) fn rewrite_ctx(
+        &self,
+        uri: &str,
+        ctx: &mut EngineCtx,
+        deadline: Option<Instant>,
+    ) -> Result<Outcome, EngineError> {
+        self.rewrite_traced(uri, ctx, deadline, None)
+    }
+
+    /// Evaluate the given URI like [`ExprGroup::rewrite_ctx`], recording
+    /// every matched rule, shift and resolution into the [`Trace`].
+    pub(crate) fn rewrite_traced(
+        &self,
+        uri: &str,
+        ctx: &mut EngineCtx,
+        deadline: Option<Instant>,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<Outcome, EngineError> {
         let mut next_index = 0;
         let mut iterations = 0;
+        let mut applied = 0;
+        let mut pass = 1;
+        let mut last = false;
+        // set once a `[C]` rule failed to match
+        let mut chain_broken = false;
 
         let (mut uri, query) = extra::split_query(uri);
-        while iterations < self.max_iterations {
+        let mut query = query.to_owned();
+        if !self.in_scope(&uri) {
+            return Ok(Outcome::new(Rewrite::Uri(uri).with_query(&query), false));
+        }
+        loop {
+            if iterations == self.max_iterations {
+                return Err(EngineError::TooManyIterations(self.max_iterations));
+            }
             iterations += 1;
+            // set while skipping the remaining members of a broken chain
+            let mut broken = false;
+            let candidates = self.candidates(&uri);
             let Some((index, rule, new_uri)) = self
                 .rules
                 .iter()
                 .enumerate()
                 .skip(next_index)
-                .find_map(|(i, r)| Some((i, r, r.try_rewrite(&uri)?)))
+                .find_map(|(i, r)| {
+                    let candidate = candidates
+                        .as_ref()
+                        .is_none_or(|c| c.contains(PatternID::new_unchecked(i)));
+                    let skipped = broken || r.pass() > pass;
+                    if skipped || !candidate {
+                        chain_broken |= !skipped && r.is_chained();
+                        broken = r.is_chained();
+                        return None;
+                    }
+                    if deadline.is_some_and(|d| Instant::now() >= d) {
+                        return Some(Err(EngineError::Timeout));
+                    }
+                    match r.try_rewrite_ctx(&uri, ctx) {
+                        Some(new_uri) if r.is_passthrough() => Some(Ok((i, r, new_uri))),
+                        Some(new_uri) => Some(Ok((i, r, self.with_base(new_uri)))),
+                        None => {
+                            chain_broken |= r.is_chained();
+                            broken = r.is_chained();
+                            None
+                        }
+                    }
+                })
+                .transpose()?
             else {
                 break;
             };
 
-            uri = new_uri;
+            applied += 1;
+            if let Some(trace) = trace.as_deref_mut() {
+                let captures = rule.try_match(&uri).map(|(c, _)| c).unwrap_or_default();
+                trace.push(TraceEvent::RuleMatched {
+                    rule: index,
+                    captures,
+                    uri: new_uri.clone(),
+                });
+            }
+            uri = match new_uri.split_once('?') {
+                Some((path, subst)) => {
+                    query = match rule.query_append() {
+                        true => extra::merge_query(subst, &query),
+                        false => subst.to_owned(),
+                    };
+                    path.to_owned()
+                }
+                None => new_uri,
+            };
             next_index = index + 1;
+            if let Some(resolve) = rule.resolve() {
+                let rewrite = match resolve {
+                    RuleResolve::Status(status) => Rewrite::StatusCode(*status),
+                    redirect => {
+                        let status = redirect.status(self.method_preserving);
+                        Rewrite::Redirect(uri, status).with_query(&query)
+                    }
+                };
+                if let Some(trace) = trace.as_deref_mut() {
+                    let rewrite = rewrite.clone();
+                    trace.push(TraceEvent::Resolved {
+                        rule: index,
+                        rewrite,
+                    });
+                }
+                return Ok(Outcome::new(rewrite, false).with_applied(applied));
+            }
             if let Some(shift) = rule.shift() {
+                if let Some(trace) = trace.as_deref_mut() {
+                    let shift = shift.clone();
+                    trace.push(TraceEvent::Shift { rule: index, shift });
+                }
                 match shift {
-                    RuleShift::Next => next_index = 0,
-                    RuleShift::Last => break,
-                    RuleShift::End => return Ok(Rewrite::EndUri(uri).with_query(query)),
+                    RuleShift::Next => {
+                        next_index = 0;
+                        pass += 1;
+                    }
+                    RuleShift::Last => {
+                        last = true;
+                        break;
+                    }
+                    RuleShift::End => {
+                        let rewrite = Rewrite::EndUri(uri).with_query(&query);
+                        return Ok(Outcome::new(rewrite, false).with_applied(applied));
+                    }
                     RuleShift::Skip(shift) => next_index += *shift as usize,
                 }
                 continue;
             }
-            if let Some(resolve) = rule.resolve() {
-                match resolve {
-                    RuleResolve::Status(status) => return Ok(Rewrite::StatusCode(*status)),
-                    RuleResolve::Redirect(status) => {
-                        return Ok(Rewrite::Redirect(uri, *status).with_query(query));
-                    }
-                }
-            }
         }
+        Ok(Outcome::new(Rewrite::Uri(uri).with_query(&query), last)
+            .with_applied(applied)
+            .with_broken_chain(chain_broken))
+    }
+
+    /// Returns true if any rule evaluated on the first pass is chained
+    /// to the next one with `[C]`.
+    pub(crate) fn has_chain(&self) -> bool {
+        self.rules.iter().any(|r| r.pass() <= 1 && r.is_chained())
+    }
+}
+
+/// Serialized form of an [`ExprGroup`], rebuilding the combined rule
+/// patterns when deserialized.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GroupRepr {
+    #[serde(default)]
+    conditions: Vec<Condition>,
+    rules: Vec<Rule>,
+    enabled: bool,
+    scope: Option<String>,
+    base: Option<String>,
+    max_iterations: usize,
+    method_preserving: bool,
+}
+
+#[cfg(feature = "serde")]
+impl From<ExprGroup> for GroupRepr {
+    fn from(group: ExprGroup) -> Self {
+        Self {
+            conditions: group.conditions,
+            rules: group.rules,
+            enabled: group.enabled,
+            scope: group.scope,
+            base: group.base,
+            max_iterations: group.max_iterations,
+            method_preserving: group.method_preserving,
+        }
+    }
+}
 
-        match iterations >= self.max_iterations {
-            true => Err(EngineError::TooManyIterations),
-            false => Ok(Rewrite::Uri(uri).with_query(query)),
+#[cfg(feature = "serde")]
+impl From<GroupRepr> for ExprGroup {
+    fn from(repr: GroupRepr) -> Self {
+        Self {
+            conditions: repr.conditions,
+            set: rule::compile_set(&repr.rules),
+            rules: repr.rules,
+            enabled: repr.enabled,
+            scope: repr.scope,
+            base: repr.base,
+            max_iterations: repr.max_iterations.max(1),
+            method_preserving: repr.method_preserving,
         }
     }
 }
 
+/// Programmatic builder for an [`ExprGroup`] that skips text parsing.
+///
+/// # Example
+///
+/// ```
+/// use std::str::FromStr;
+/// use mod_rewrite::{Condition, GroupBuilder, Rule};
+///
+/// let group = GroupBuilder::new()
+///     .condition(Condition::from_str("%{HTTP_HOST} =old.example.com").unwrap())
+///     .rule(Rule::from_str("^/(.*) https://example.com/$1 [R=301]").unwrap())
+///     .build();
+/// ```
+#[derive(Debug, Default)]
+pub struct GroupBuilder {
+    expressions: Vec<Expression>,
+}
+
+impl GroupBuilder {
+    /// Create an empty [`GroupBuilder`].
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a [`Condition`] guarding every rule of the group.
+    pub fn condition(mut self, condition: Condition) -> Self {
+        self.expressions.push(Expression::Condition(condition));
+        self
+    }
+
+    /// Add a [`Rule`] evaluated in order after all previous rules.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.expressions.push(Expression::Rule(rule));
+        self
+    }
+
+    /// Enable or disable the group like a `RewriteEngine` expression.
+    pub fn state(mut self, state: State) -> Self {
+        self.expressions.push(Expression::State(state));
+        self
+    }
+
+    /// Limit the group to uri paths beginning with the prefix like
+    /// a `RewriteScope` expression.
+    pub fn scope(mut self, prefix: &str) -> Self {
+        self.expressions.push(Expression::Scope(prefix.to_owned()));
+        self
+    }
+
+    /// Prepend the path to relative substitutions like a `RewriteBase`
+    /// expression.
+    pub fn base(mut self, path: &str) -> Self {
+        self.expressions.push(Expression::Base(path.to_owned()));
+        self
+    }
+
+    /// Produce the [`ExprGroup`] from all collected expressions.
+    #[inline]
+    pub fn build(self) -> ExprGroup {
+        ExprGroup::new(self.expressions)
+    }
+}
+
 /// Categorization and deserializion for [`ExprGroup`] instances
 /// made from a list of flat [`Expression`] instances.
 ///
@@ -154,67 +801,258 @@ impl FromStr for ExpressionList {
     type Err = ExpressionError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut list = Vec::new();
-        let mut group: Vec<Expression> = Vec::new();
-        for line in s
-            .split('\n')
-            .map(|s| s.trim())
-            .filter(|s| !s.starts_with("//"))
-        {
+        let mut parser = RuleParser::new();
+        parser.feed(s)?;
+        Ok(parser.into_list())
+    }
+}
+
+/// Stateful parser accumulating [`Expression`]s across multiple chunks
+/// of text before building an [`Engine`](crate::Engine).
+///
+/// Unlike [`Engine::add_rules`](crate::Engine::add_rules), a group is not
+/// closed at the end of each chunk, so conditions fed in one call still
+/// guard the rule supplied by the next. Blank lines at the start or end
+/// of a chunk are ignored, and only blank lines within a chunk separate
+/// groups.
+///
+/// # Example
+///
+/// ```
+/// use mod_rewrite::RuleParser;
+///
+/// let mut parser = RuleParser::new();
+/// parser
+///     .feed("RewriteCond %{HTTP_HOST} =old.example.com")
+///     .and_then(|p| p.feed("RewriteRule ^/(.*) https://example.com/$1 [R=301]"))
+///     .expect("failed to process rules");
+/// let engine = parser.finish();
+/// ```
+#[derive(Debug, Default)]
+pub struct RuleParser {
+    list: Vec<Vec<Expression>>,
+    group: Vec<Expression>,
+    mode: ParseMode,
+}
+
+impl RuleParser {
+    /// Create an empty [`RuleParser`].
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configure how unknown rule and condition flags are handled
+    /// in every chunk fed afterwards.
+    ///
+    /// Default is [`ParseMode::Strict`]
+    pub fn mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Parse another chunk of [`Expression`]s, continuing the group
+    /// left open by the previous chunk.
+    ///
+    /// Parse errors are wrapped in [`ExpressionError::AtLine`] along with
+    /// the 1-based line number within the chunk of the failing expression.
+    pub fn feed(&mut self, s: &str) -> Result<&mut Self, ExpressionError> {
+        let offset = s[..s.len() - s.trim_start().len()].matches('\n').count();
+        for (n, line) in lines(s.trim()) {
             if line.is_empty() {
-                list.push(group.clone());
-                group.clear();
+                self.close();
                 continue;
             }
-            let expr = Expression::from_str(line)?;
-            if matches!(expr, Expression::State(_))
-                || (matches!(expr, Expression::Condition(_))
-                    && group
-                        .last()
-                        .is_some_and(|e| matches!(e, Expression::Rule(_))))
+            let expr =
+                Expression::parse(&line, self.mode).map_err(|err| ExpressionError::AtLine {
+                    line: n + offset,
+                    source: Box::new(err),
+                })?;
+            if matches!(
+                expr,
+                Expression::State(_) | Expression::Scope(_) | Expression::Base(_)
+            ) || (matches!(expr, Expression::Condition(_))
+                && self
+                    .group
+                    .last()
+                    .is_some_and(|e| matches!(e, Expression::Rule(_))))
             {
-                list.push(group.clone());
-                group.clear();
+                self.close();
             }
-            group.push(expr);
+            self.group.push(expr);
         }
-        if !group.is_empty() {
-            list.push(group);
+        Ok(self)
+    }
+
+    /// Close the currently open group, if any.
+    fn close(&mut self) {
+        if !self.group.is_empty() {
+            self.list.push(std::mem::take(&mut self.group));
         }
-        Ok(Self(list.into_iter().filter(|g| !g.is_empty()).collect()))
     }
+
+    pub(crate) fn into_list(mut self) -> ExpressionList {
+        self.close();
+        ExpressionList(self.list)
+    }
+
+    /// Close the last group and build an [`Engine`](crate::Engine)
+    /// from every chunk fed so far.
+    #[inline]
+    pub fn finish(self) -> crate::Engine {
+        crate::Engine::from_groups(self.into_list().groups())
+    }
+}
+
+/// Returns true if the trimmed line is a comment ignored by the parser.
+#[inline]
+fn is_comment(line: &str) -> bool {
+    line.starts_with("//") || line.starts_with('#')
+}
+
+/// Strip a trailing `#` comment separated from the expression by
+/// whitespace, ignoring any `#` within a quoted token where `\"` or `\'`
+/// escapes the quote.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut prev = ' ';
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, '#') if prev.is_whitespace() => return line[..i].trim_end(),
+            (None, '"' | '\'') if prev.is_whitespace() => quote = Some(c),
+            (Some(q), '\\') if chars.peek().is_some_and(|(_, c)| *c == q) => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+        prev = c;
+    }
+    line
+}
+
+/// Strip the backslash of a line continued on the next line, unless
+/// the backslash is itself escaped.
+#[inline]
+fn strip_continuation(line: &str) -> Option<&str> {
+    let escapes = line.len() - line.trim_end_matches('\\').len();
+    (escapes % 2 == 1).then(|| line[..line.len() - 1].trim_end())
+}
+
+/// Iterate over trimmed logical lines along with the 1-based line number
+/// they start on, skipping comments and joining lines ending with a `\`.
+pub(crate) fn lines(s: &str) -> impl Iterator<Item = (usize, Cow<'_, str>)> {
+    let mut physical = s
+        .split('\n')
+        .map(|line| line.trim())
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !is_comment(line));
+    std::iter::from_fn(move || {
+        let (n, line) = physical.next()?;
+        let Some(mut joined) = strip_continuation(line).map(str::to_owned) else {
+            return Some((n, Cow::Borrowed(strip_comment(line))));
+        };
+        for (_, next) in physical.by_ref() {
+            let continued = strip_continuation(next);
+            joined.push(' ');
+            joined.push_str(continued.unwrap_or(next));
+            if continued.is_none() {
+                break;
+            }
+        }
+        Some((n, Cow::Owned(strip_comment(&joined).to_owned())))
+    })
 }
 
 /// All possible expression types allowed within `mod_rewrite`
 ///
-/// Will eventually support RewriteEngine/RewriteCond/RewriteRule/RewriteBase
+/// Supports RewriteEngine/RewriteCond/RewriteRule/RewriteBase
+///
+/// `RewriteScope` is a non-Apache extension that limits the group
+/// it starts to uri paths beginning with the given prefix, skipping
+/// it before any condition or rule pattern is evaluated.
 #[derive(Clone, Debug)]
 pub enum Expression {
     Condition(Condition),
     Rule(Rule),
     State(State),
+    Scope(String),
+    /// Base path prepended to relative substitutions of the group it starts.
+    Base(String),
+    /// Comment line retained by [`Expression::parse_preserving`].
+    Comment(String),
+    /// Blank line retained by [`Expression::parse_preserving`].
+    Blank,
 }
 
-impl FromStr for Expression {
-    type Err = ExpressionError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Expression {
+    /// Parse a single expression, skipping unknown rule and condition
+    /// flags rather than failing when `mode` is [`ParseMode::Lenient`].
+    pub fn parse(s: &str, mode: ParseMode) -> Result<Self, ExpressionError> {
         let (ident, expr) = s
             .split_once(char::is_whitespace)
             .ok_or(ExpressionError::MissingIdentifier)?;
         match ident.to_lowercase().as_str() {
-            "rule" | "rewrite" | "rewriterule" => Ok(Self::Rule(Rule::from_str(expr)?)),
-            "cond" | "condition" | "rewritecond" => Ok(Self::Condition(Condition::from_str(expr)?)),
+            "rule" | "rewrite" | "rewriterule" => Ok(Self::Rule(Rule::parse(expr, mode)?)),
+            "cond" | "condition" | "rewritecond" => {
+                Ok(Self::Condition(Condition::parse(expr, mode)?))
+            }
             "state" | "engine" | "rewriteengine" => Ok(Self::State(State::from_str(expr)?)),
+            "scope" | "rewritescope" => Ok(Self::Scope(expr.trim().to_owned())),
+            "base" | "rewritebase" => Ok(Self::Base(expr.trim().to_owned())),
             _ => Err(ExpressionError::InvalidIdentifier(s.to_owned())),
         }
     }
+
+    /// Parse every line into an [`Expression`], retaining comments and
+    /// blank lines rather than discarding them.
+    ///
+    /// Intended for editing tools that re-render a configuration after
+    /// changing individual expressions, by joining the [`Display`](fmt::Display)
+    /// output of each item with newlines.
+    pub fn parse_preserving(s: &str) -> Result<Vec<Self>, ExpressionError> {
+        s.split('\n')
+            .map(|line| line.trim())
+            .map(|line| match line {
+                "" => Ok(Self::Blank),
+                line if is_comment(line) => Ok(Self::Comment(line.to_owned())),
+                line => Self::from_str(strip_comment(line)),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Condition(cond) => write!(f, "RewriteCond {cond}"),
+            Self::Rule(rule) => write!(f, "RewriteRule {rule}"),
+            Self::State(state) => write!(f, "RewriteEngine {state}"),
+            Self::Scope(prefix) => write!(f, "RewriteScope {prefix}"),
+            Self::Base(path) => write!(f, "RewriteBase {path}"),
+            Self::Comment(comment) => f.write_str(comment),
+            Self::Blank => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, ParseMode::Strict)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::context::RequestCtx;
+
     #[test]
     fn test_groups() {
         let groups = ExpressionList::from_str(
@@ -235,16 +1073,249 @@ mod tests {
         assert_eq!(groups.len(), 4);
         assert_eq!(groups[0].conditions.len(), 1);
         assert_eq!(groups[0].rules.len(), 1);
-        assert_eq!(groups[0].enabled, true);
+        assert!(groups[0].enabled);
         assert_eq!(groups[1].conditions.len(), 0);
         assert_eq!(groups[1].rules.len(), 1);
-        assert_eq!(groups[1].enabled, true);
+        assert!(groups[1].enabled);
         assert_eq!(groups[2].conditions.len(), 1);
         assert_eq!(groups[2].rules.len(), 1);
-        assert_eq!(groups[2].enabled, true);
+        assert!(groups[2].enabled);
         assert_eq!(groups[3].conditions.len(), 0);
         assert_eq!(groups[3].rules.len(), 1);
-        assert_eq!(groups[3].enabled, false);
+        assert!(!groups[3].enabled);
+    }
+
+    #[test]
+    fn test_parser_feed() {
+        let mut parser = RuleParser::new();
+        parser
+            .feed(
+                r#"
+                RewriteRule ^/a$ /b
+                RewriteCond %{REQUEST_METHOD} =POST
+            "#,
+            )
+            .unwrap()
+            .feed("RewriteRule ^/(.*) /post/$1\n")
+            .unwrap();
+
+        let groups = parser.into_list().groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1].conditions.len(), 1);
+        assert_eq!(groups[1].rules.len(), 1);
+
+        let mut parser = RuleParser::new();
+        parser.feed("RewriteCond %{REQUEST_METHOD} =POST").unwrap();
+        parser.feed("RewriteRule ^/(.*) /post/$1").unwrap();
+        let engine = parser.finish();
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_method("POST"));
+        let r = engine.rewrite_ctx("/form", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/post/form"));
+
+        let mut ctx = EngineCtx::default().with_ctx(RequestCtx::default().request_method("GET"));
+        let r = engine.rewrite_ctx("/form", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/form"));
+    }
+
+    #[test]
+    fn test_base() {
+        let groups = ExpressionList::from_str(
+            r#"
+            RewriteBase /app/
+            RewriteRule ^/old/(.*)$ new/$1 [NE]
+            RewriteRule ^/abs$ /absolute
+            RewriteRule ^/ext$ https://example.com/ext [R]
+            RewriteRule ^/rel$ page.html [R]
+
+            RewriteBase /site
+            RewriteRule ^/(.*)$ index.php?path=$1
+        "#,
+        )
+        .unwrap()
+        .groups();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].base(), Some("/app/"));
+        let r = groups[0].rewrite("/old/a/b").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/app/new/a/b"));
+        let r = groups[0].rewrite("/abs").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/absolute"));
+        let r = groups[0].rewrite("/ext").unwrap();
+        assert_eq!(r.location(), Some("https://example.com/ext"));
+        let r = groups[0].rewrite("/rel").unwrap();
+        assert_eq!(r.location(), Some("/app/page.html"));
+
+        let r = groups[1].rewrite("/x").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/site/index.php?path=x"));
+        assert_eq!(
+            Expression::from_str("base /site").unwrap().to_string(),
+            "RewriteBase /site"
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let groups = ExpressionList::from_str(
+            r#"
+            # redirect legacy pages
+            RewriteCond %{HTTP_HOST} "=old #1" # quoted hash
+                # indented comment
+            RewriteRule ^/legacy/(.*)$ /new/$1 [R=301] # trailing comment
+
+            // alternative comment style
+            RewriteRule ^/a#b$ /c
+            RewriteRule ^/d$ "/e \" #x" # escaped quote
+        "#,
+        )
+        .unwrap()
+        .groups();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].conditions.len(), 1);
+        assert_eq!(groups[0].rules.len(), 1);
+        assert_eq!(
+            Expression::Condition(groups[0].conditions[0].clone()).to_string(),
+            "RewriteCond %{HTTP_HOST} \"=old #1\""
+        );
+        assert_eq!(groups[1].rules.len(), 2);
+        assert!(matches!(groups[1].rewrite("/a#b"), Ok(Rewrite::Uri(uri)) if uri == "/c"));
+        assert!(matches!(groups[1].rewrite("/d"), Ok(Rewrite::Uri(uri)) if uri == "/e \" #x"));
+    }
+
+    #[test]
+    fn test_absolute_url() {
+        assert!(Rewrite::Uri("http://localhost/path".to_owned()).is_absolute_url());
+        assert!(Rewrite::Redirect("https://example.com".to_owned(), 302).is_absolute_url());
+        assert!(Rewrite::Uri("//localhost/path".to_owned()).is_absolute_url());
+        assert!(!Rewrite::Uri("/path".to_owned()).is_absolute_url());
+        assert!(!Rewrite::Uri("/path?next=http://localhost".to_owned()).is_absolute_url());
+        assert!(!Rewrite::EndUri("relative/path:1".to_owned()).is_absolute_url());
+        assert!(!Rewrite::StatusCode(403).is_absolute_url());
+    }
+
+    #[test]
+    fn test_redirect_location() {
+        let groups = ExpressionList::from_str(
+            r"RewriteRule ^/blog/(\d+)$ https://blog.example.com/post?id=$1 [R=301]",
+        )
+        .unwrap()
+        .groups();
+        let r = groups[0].rewrite("/blog/42?ref=home").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(_, 301)));
+        assert_eq!(r.location(), Some("https://blog.example.com/post?id=42"));
+        assert_eq!(Rewrite::Uri("/blog/42".to_owned()).location(), None);
+    }
+
+    fn group_matches(rules: &str, method: &str, uri: &str) -> bool {
+        let groups = ExpressionList::from_str(rules).unwrap().groups();
+        let req = RequestCtx::default()
+            .request_method(method)
+            .request_uri(uri);
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        groups[0].match_conditions(&mut ctx)
+    }
+
+    #[test]
+    fn test_negated_or() {
+        // evaluates as `(!A) || B`
+        let rules = r#"
+            RewriteCond %{REQUEST_METHOD} !=GET [OR]
+            RewriteCond %{REQUEST_URI} =/b
+            RewriteRule /(.*) /matched
+        "#;
+        assert!(!group_matches(rules, "GET", "/a"));
+        assert!(group_matches(rules, "GET", "/b"));
+        assert!(group_matches(rules, "POST", "/a"));
+        assert!(group_matches(rules, "POST", "/b"));
+
+        // evaluates as `A || (!B)`
+        let rules = r#"
+            RewriteCond %{REQUEST_METHOD} =GET [OR]
+            RewriteCond %{REQUEST_URI} !=/b
+            RewriteRule /(.*) /matched
+        "#;
+        assert!(group_matches(rules, "GET", "/a"));
+        assert!(group_matches(rules, "GET", "/b"));
+        assert!(group_matches(rules, "POST", "/a"));
+        assert!(!group_matches(rules, "POST", "/b"));
+    }
+
+    #[test]
+    fn test_or_precedence() {
+        // evaluates as `(A || B) && C`
+        let rules = r#"
+            RewriteCond %{REQUEST_METHOD} =GET [OR]
+            RewriteCond %{REQUEST_METHOD} =HEAD
+            RewriteCond %{REQUEST_URI} =/b
+            RewriteRule /(.*) /matched
+        "#;
+        assert!(group_matches(rules, "GET", "/b"));
+        assert!(group_matches(rules, "HEAD", "/b"));
+        assert!(!group_matches(rules, "POST", "/b"));
+        assert!(!group_matches(rules, "GET", "/a"));
+
+        // evaluates as `A && (B || C)`
+        let rules = r#"
+            RewriteCond %{REQUEST_URI} =/b
+            RewriteCond %{REQUEST_METHOD} =GET [OR]
+            RewriteCond %{REQUEST_METHOD} =HEAD
+            RewriteRule /(.*) /matched
+        "#;
+        assert!(group_matches(rules, "HEAD", "/b"));
+        assert!(!group_matches(rules, "POST", "/b"));
+        assert!(!group_matches(rules, "GET", "/a"));
+
+        // trailing `[OR]` still requires its chain to be met
+        let rules = r#"
+            RewriteCond %{REQUEST_URI} =/b
+            RewriteCond %{REQUEST_METHOD} =GET [OR]
+            RewriteRule /(.*) /matched
+        "#;
+        assert!(group_matches(rules, "GET", "/b"));
+        assert!(!group_matches(rules, "POST", "/b"));
+    }
+
+    #[test]
+    fn test_cond_backrefs() {
+        let rules = r#"
+            RewriteCond %{REQUEST_URI} ^/(\w+)/(\w*)
+            RewriteCond %2 .
+            RewriteRule /(.*) /matched
+        "#;
+        assert!(group_matches(rules, "GET", "/user/42"));
+        assert!(!group_matches(rules, "GET", "/user/"));
+        assert!(!group_matches(rules, "GET", "/"));
+
+        let rules = r#"
+            RewriteCond %{REQUEST_URI} ^/(\w+)
+            RewriteCond %{REQUEST_METHOD}:%1 =POST:upload
+            RewriteRule /(.*) /matched
+        "#;
+        assert!(group_matches(rules, "POST", "/upload"));
+        assert!(!group_matches(rules, "POST", "/download"));
+    }
+
+    #[test]
+    fn test_parse_preserving() {
+        let config = "// redirect legacy host
+RewriteCond %{HTTP_HOST} =old.example.com [NC]
+RewriteRule ^/(.*) https://example.com/$1 [R=301]
+
+// static files
+RewriteEngine Off
+RewriteRule ^/static/(.*) /files/$1 [NE,L]
+";
+        let exprs = Expression::parse_preserving(config).unwrap();
+        assert_eq!(exprs.len(), 8);
+        assert!(matches!(&exprs[0], Expression::Comment(c) if c == "// redirect legacy host"));
+        assert!(matches!(exprs[3], Expression::Blank));
+        assert!(matches!(exprs[7], Expression::Blank));
+
+        let rendered: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+        assert_eq!(rendered.join("\n"), config);
+
+        let groups = ExprGroup::new(exprs);
+        assert_eq!(groups.rules().len(), 2);
     }
 
     #[test]
@@ -273,12 +1344,70 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_skip() {
+        let group = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/a$ /b        [S=1]
+            RewriteRule ^/b$ /skipped
+            RewriteRule ^/b$ /c
+        "#,
+        )
+        .unwrap()
+        .groups()
+        .remove(0);
+        let r = group.rewrite("/a").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/c"));
+
+        // skips are relative to the absolute position of the matched rule
+        let group = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/a$ /b
+            RewriteRule ^/b$ /c        [S=1]
+            RewriteRule ^/c$ /skipped
+            RewriteRule ^/c$ /d
+        "#,
+        )
+        .unwrap()
+        .groups()
+        .remove(0);
+        let r = group.rewrite("/a").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/d"));
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let rule = Rule::from_str("^/blocked/(.*) - [F]").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/blocked/page"),
+            Some("/blocked/page".to_owned())
+        );
+
+        let group = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/blocked/(.*) -        [F]
+            RewriteRule ^/keep         -        [E=KEPT:yes]
+            RewriteRule ^/(keep)       /kept/$1
+        "#,
+        )
+        .unwrap()
+        .groups()
+        .remove(0);
+        let r = group.rewrite("/blocked/page").unwrap();
+        assert!(matches!(r, Rewrite::StatusCode(403)));
+
+        let mut ctx = EngineCtx::default();
+        let r = group.rewrite_ctx("/keep?a=b", &mut ctx, None).unwrap();
+        assert!(matches!(r.rewrite(), Rewrite::Uri(uri) if uri == "/kept/keep?a=b"));
+        assert_eq!(ctx.env_vars().get("KEPT").map(String::as_str), Some("yes"));
+    }
+
     #[test]
     fn test_query() {
         let groups = ExpressionList::from_str(
             r#"
             RewriteRule /static/(.*) /files/$1 [NE,L]
-            RewriteRule /(.*)        /index?page=$1
+            RewriteRule /(.*)        /index?page=$1 [QSA]
         "#,
         )
         .unwrap()
@@ -294,6 +1423,91 @@ mod tests {
         assert!(matches!(r, Rewrite::Uri(uri) if uri == "/index?page=1%2F2%2F3&a=b"));
     }
 
+    #[test]
+    fn test_uri() {
+        assert_eq!(Rewrite::Uri("/a".to_owned()).uri(), Some("/a"));
+        assert_eq!(Rewrite::EndUri("/b".to_owned()).uri(), Some("/b"));
+        assert_eq!(Rewrite::Redirect("/c".to_owned(), 302).uri(), None);
+        assert_eq!(Rewrite::StatusCode(403).uri(), None);
+    }
+
+    #[test]
+    fn test_is_terminal() {
+        assert!(!Rewrite::Uri("/a".to_owned()).is_terminal());
+        assert!(Rewrite::EndUri("/a".to_owned()).is_terminal());
+        assert!(Rewrite::Redirect("/a".to_owned(), 302).is_terminal());
+        assert!(Rewrite::StatusCode(403).is_terminal());
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_into_response() {
+        assert!(Rewrite::Uri("/a".to_owned()).into_response().is_none());
+        assert!(Rewrite::EndUri("/a".to_owned()).into_response().is_none());
+
+        let response = Rewrite::Redirect("/new".to_owned(), 302)
+            .into_response()
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::FOUND);
+        assert_eq!(response.headers()[http::header::LOCATION], "/new");
+
+        let response = Rewrite::StatusCode(410).into_response().unwrap();
+        assert_eq!(response.status(), http::StatusCode::GONE);
+        assert!(response.headers().is_empty());
+    }
+
+    #[test]
+    fn test_query_passes() {
+        let group = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/a$ /b?x=1
+            RewriteRule ^/b$ /c
+            RewriteRule ^/c$ /d?y=2 [QSA,C]
+            RewriteRule ^/d$ /e?z=3 [QSA]
+        "#,
+        )
+        .unwrap()
+        .groups()
+        .remove(0);
+
+        let r = group.rewrite("/a?q=0").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/e?z=3&y=2&x=1"));
+
+        let r = group.rewrite("/b?q=0").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/e?z=3&y=2&q=0"));
+
+        let group = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/a$ /b?x=1
+            RewriteRule ^/b$ /c?y=2
+        "#,
+        )
+        .unwrap()
+        .groups()
+        .remove(0);
+        let r = group.rewrite("/a?q=0").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/c?y=2"));
+    }
+
+    #[test]
+    fn test_pass() {
+        let groups = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/loop$   /done  [PASS=2]
+            RewriteRule ^/start$  /loop  [N]
+        "#,
+        )
+        .unwrap()
+        .groups();
+
+        let group = &groups[0];
+        let r = group.rewrite("/start").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/done"));
+
+        let r = group.rewrite("/loop").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/loop"));
+    }
+
     #[test]
     fn test_overflow() {
         let groups = ExpressionList::from_str(
@@ -310,6 +1524,80 @@ mod tests {
         let group = &groups[0];
 
         let r = group.rewrite("/skip");
-        assert!(matches!(r, Err(EngineError::TooManyIterations)));
+        assert!(matches!(r, Err(EngineError::TooManyIterations(10))));
+
+        // the final search finding no rule fits within the limit
+        let group = ExpressionList::from_str("RewriteRule ^/a$ /b")
+            .unwrap()
+            .groups()
+            .remove(0)
+            .max_iterations(2);
+        assert!(matches!(group.rewrite("/a"), Ok(Rewrite::Uri(uri)) if uri == "/b"));
+        let group = group.max_iterations(1);
+        assert!(matches!(
+            group.rewrite("/a"),
+            Err(EngineError::TooManyIterations(1))
+        ));
+
+        let group = group.max_iterations(0);
+        assert!(matches!(group.rewrite("/c"), Ok(Rewrite::Uri(uri)) if uri == "/c"));
+    }
+
+    #[test]
+    fn test_next() {
+        let group = ExpressionList::from_str("RewriteRule ^/(a+)a$ /$1 [N]")
+            .unwrap()
+            .groups()
+            .remove(0);
+        assert!(matches!(group.rewrite("/aaa"), Ok(Rewrite::Uri(uri)) if uri == "/a"));
+        assert!(matches!(group.rewrite("/b"), Ok(Rewrite::Uri(uri)) if uri == "/b"));
+
+        // two restarts along with the final search finding no rule
+        let group = group.max_iterations(3);
+        assert!(matches!(group.rewrite("/aaa"), Ok(Rewrite::Uri(uri)) if uri == "/a"));
+        let group = group.max_iterations(2);
+        assert!(matches!(
+            group.rewrite("/aaa"),
+            Err(EngineError::TooManyIterations(2))
+        ));
+    }
+
+    #[test]
+    fn test_rule_set() {
+        let mut rules: Vec<String> = (0..50)
+            .map(|i| format!("RewriteRule ^/page/{i}$ /target/{i}"))
+            .collect();
+        rules.extend([
+            "RewriteRule ^/skip$ /skipped [S=1]".to_owned(),
+            "RewriteRule ^/skip /never".to_owned(),
+            "RewriteRule ^/(chain|other)$ /$1/a [C]".to_owned(),
+            "RewriteRule ^/chain/a$ /chain/b".to_owned(),
+            "RewriteRule ^/other/x$ /never".to_owned(),
+            "RewriteRule ^/UPPER$ /lower [NC]".to_owned(),
+        ]);
+        let group = ExpressionList::from_str(&rules.join("\n"))
+            .unwrap()
+            .groups()
+            .remove(0);
+        assert!(group.set.is_some());
+
+        let mut linear = group.clone();
+        linear.set = None;
+        for uri in [
+            "/page/7",
+            "/page/49?q",
+            "/skip",
+            "/chain",
+            "/other",
+            "/upper",
+            "/none",
+        ] {
+            let expected = linear.rewrite(uri).unwrap();
+            assert_eq!(group.rewrite(uri).unwrap(), expected, "{uri}");
+        }
+        assert!(matches!(group.rewrite("/page/7"), Ok(Rewrite::Uri(uri)) if uri == "/target/7"));
+        assert!(matches!(group.rewrite("/chain"), Ok(Rewrite::Uri(uri)) if uri == "/chain/b"));
+        assert!(matches!(group.rewrite("/other"), Ok(Rewrite::Uri(uri)) if uri == "/other/a"));
+        assert!(matches!(group.rewrite("/upper"), Ok(Rewrite::Uri(uri)) if uri == "/lower"));
     }
 }
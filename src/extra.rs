@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{borrow::Cow, fmt, str::FromStr};
 
 use super::error::ExpressionError;
 
@@ -22,6 +22,83 @@ pub(crate) fn join_query(mut uri: String, query: &str) -> String {
     uri
 }
 
+/// Join two query strings with `&`, skipping either if empty.
+#[inline]
+pub(crate) fn merge_query(first: &str, second: &str) -> String {
+    match (first.is_empty(), second.is_empty()) {
+        (true, _) => second.to_owned(),
+        (_, true) => first.to_owned(),
+        _ => format!("{first}&{second}"),
+    }
+}
+
+/// Normalize percent-encoding of the uri path following RFC 3986, leaving
+/// the query string untouched.
+///
+/// Escapes are uppercased (`%2f` becomes `%2F`) and escaped unreserved
+/// characters are decoded (`%7E` becomes `~`).
+pub(crate) fn normalize_percent_encoding(uri: &str) -> Cow<'_, str> {
+    let (path, query) = uri.split_at(uri.find('?').unwrap_or(uri.len()));
+    let bytes = path.as_bytes();
+    let mut normal = String::with_capacity(uri.len());
+    let (mut last, mut i) = (0, 0);
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        let Some(hex) = hex else {
+            i += 1;
+            continue;
+        };
+        normal.push_str(&path[last..i]);
+        let byte = std::str::from_utf8(hex)
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .unwrap_or_default();
+        match byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            true => normal.push(byte as char),
+            false => normal.push_str(&format!("%{byte:02X}")),
+        }
+        i += 3;
+        last = i;
+    }
+    normal.push_str(&path[last..]);
+    normal.push_str(query);
+    match normal == uri {
+        true => Cow::Borrowed(uri),
+        false => Cow::Owned(normal),
+    }
+}
+
+/// Check if the uri is a full url including a scheme (`https://host/path`)
+/// or a network-path reference (`//host/path`) rather than a server path.
+pub(crate) fn is_absolute_url(uri: &str) -> bool {
+    if uri.starts_with("//") {
+        return true;
+    }
+    let Some((scheme, _)) = uri.split_once(':') else {
+        return false;
+    };
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || ['+', '-', '.'].contains(&c))
+}
+
+/// Split a full url into its scheme and authority (`https://host`) and
+/// the remaining path and query.
+///
+/// Uris without a scheme produce an empty origin.
+pub(crate) fn split_origin(uri: &str) -> (&str, &str) {
+    let Some((scheme, rest)) = uri.split_once("://") else {
+        return ("", uri);
+    };
+    if !is_absolute_url(&uri[..scheme.len() + 1]) {
+        return ("", uri);
+    }
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    uri.split_at(scheme.len() + 3 + end)
+}
+
 /// Singular `RewriteEngine` expression definition.
 ///
 /// Considered a breakpoint for [`ExprGroup`](super::ExprGroup)
@@ -34,6 +111,15 @@ pub enum State {
     Off,
 }
 
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::On => "On",
+            Self::Off => "Off",
+        })
+    }
+}
+
 impl FromStr for State {
     type Err = ExpressionError;
 
@@ -45,3 +131,14 @@ impl FromStr for State {
         }
     }
 }
+
+/// Strictness applied to unknown flags when parsing [`Rule`](super::Rule)
+/// and [`Condition`](super::Condition) expressions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Unknown flags fail the whole expression.
+    #[default]
+    Strict,
+    /// Unknown flags are skipped and collected for inspection.
+    Lenient,
+}
@@ -16,28 +16,108 @@
 //! let result = engine.rewrite(uri).unwrap();
 //! println!("{result:?}");
 //! ```
+//!
+//! # Query strings
+//!
+//! Rules only match the path of the uri. Like `mod_rewrite`, a
+//! substitution containing a query *replaces* the incoming query string,
+//! while `[QSA]` appends the incoming query after the substitution query.
+//! Substitutions without a query keep the incoming query untouched.
+//!
+//! ```
+//! use mod_rewrite::{Engine, Rewrite};
+//!
+//! let engine: Engine = r#"
+//!   RewriteRule ^/a$ /b?x=1
+//!   RewriteRule ^/c$ /d?x=1 [QSA]
+//! "#.parse().unwrap();
+//!
+//! assert_eq!(engine.rewrite("/a?y=2").unwrap(), Rewrite::Uri("/b?x=1".to_owned()));
+//! assert_eq!(engine.rewrite("/c?y=2").unwrap(), Rewrite::Uri("/d?x=1&y=2".to_owned()));
+//! ```
+//!
+//! Earlier releases merged the incoming query into every substitution
+//! query; add `[QSA]` to rules relying on that behavior.
+//!
+//! # Backreference escaping
+//!
+//! Values inserted by `$N` backreferences are percent-encoded unless the
+//! rule uses `[NE]`. A `/` is kept as-is in the path of the substitution
+//! and only encoded as `%2F` once past the `?` starting its query string.
+//!
+//! ```
+//! use mod_rewrite::{Engine, Rewrite};
+//!
+//! let engine: Engine = "RewriteRule ^/p/(.*)$ /files/$1?from=$1".parse().unwrap();
+//! let r = engine.rewrite("/p/a/b").unwrap();
+//! assert_eq!(r, Rewrite::Uri("/files/a/b?from=a%2Fb".to_owned()));
+//! ```
+//!
+//! Earlier releases encoded every `/` in backreferences; rules relying on
+//! that behavior can use `[B]`, which encodes every reserved character.
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 mod conditions;
+mod diff;
 pub mod error;
 mod expr;
 mod extra;
+mod lint;
+mod map;
 mod rule;
+#[cfg(feature = "tower")]
+mod service;
+mod trace;
 
 use conditions::EngineCtx;
 use error::{EngineError, ExpressionError};
 use expr::ExpressionList;
 
 pub use conditions::{Condition, context};
-pub use expr::{ExprGroup, Expression, Rewrite};
-pub use extra::State;
-pub use rule::Rule;
+pub use diff::{Change, EngineDiff};
+pub use expr::{
+    ExprGroup, Expression, GroupBuilder, Outcome, Rewrite, RouteMatch, RuleParser,
+    TerminationReason,
+};
+pub use extra::{ParseMode, State};
+pub use lint::Lint;
+pub use map::{MapSeparator, TextMap};
+pub use rule::{Rule, RuleFlag, RuleMod, RuleResolve, RuleShift};
+#[cfg(feature = "tower")]
+pub use service::{ResponseFuture, RewriteLayer, RewriteService};
+pub use trace::{Trace, TraceEvent};
+
+/// Redirect extension-less paths missing a trailing slash.
+const ADD_TRAILING_SLASH: &str = r"RewriteRule ^((?:/[^/.]+)+)$ $1/ [NE,R=301]";
+
+/// Redirect paths ending in a trailing slash.
+const REMOVE_TRAILING_SLASH: &str = r"RewriteRule ^(/.+)/$ $1 [NE,R=301]";
+
+/// Size of the compiled rule-set reported by [`Engine::stats`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineStats {
+    pub groups: usize,
+    pub rules: usize,
+    pub conditions: usize,
+    /// Heap memory used by all compiled rule and condition regexes in bytes.
+    pub regex_bytes: usize,
+}
 
 /// Expression Engine for Proccessing Rewrite Rules
 ///
 /// Supports a subset of [official](https://httpd.apache.org/docs/current/mod/mod_rewrite.html)
 /// `mod_rewrite` expressions.
 ///
+/// With the `serde` feature the engine can be serialized, storing rule
+/// patterns as their source and recompiling them when deserialized.
+/// Registered [`TextMap`]s are not serialized and must be added again.
+///
 /// # Example
 ///
 /// ```
@@ -55,11 +135,37 @@ pub use rule::Rule;
 /// println!("{result:?}");
 /// ```
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Engine {
     groups: Vec<ExprGroup>,
+    fail_closed: bool,
+    default: Option<Rewrite>,
+    timeout: Option<Duration>,
+    normalize: bool,
+    canonical_redirect: Option<u16>,
+    path_only: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    maps: Arc<map::Maps>,
 }
 
 impl Engine {
+    /// Create an [`EngineBuilder`] to configure a new engine.
+    #[inline]
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    /// Build an engine from already constructed [`ExprGroup`]s, evaluated
+    /// in order, without parsing any text.
+    ///
+    /// See [`GroupBuilder`] for building each group programmatically.
+    pub fn from_groups(groups: impl IntoIterator<Item = ExprGroup>) -> Self {
+        Self {
+            groups: groups.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
     /// Configure max number of loops over entire ruleset during
     /// rewrite before error
     ///
@@ -73,8 +179,183 @@ impl Engine {
         self
     }
 
+    /// Configure `[R=permanent]` and `[R=temp]` redirects to use the
+    /// method-preserving `308` and `307` statuses rather than `301` and `302`.
+    ///
+    /// Numeric statuses such as `[R=301]` are never changed.
+    ///
+    /// Default is false
+    pub fn method_preserving_redirects(mut self, enabled: bool) -> Self {
+        self.groups = self
+            .groups
+            .into_iter()
+            .map(|g| g.method_preserving_redirects(enabled))
+            .collect();
+        self
+    }
+
+    /// Configure a wall-clock budget for each rewrite, checked between rules,
+    /// after which evaluation aborts with [`EngineError::Timeout`].
+    ///
+    /// Groups matching their rule patterns as a combined set only check the
+    /// budget before the set search and before each candidate rule, so the
+    /// check is coarser there: a single set search is never interrupted and
+    /// rules excluded by it are skipped without a check.
+    ///
+    /// Default is no limit
+    pub fn timeout(mut self, budget: Duration) -> Self {
+        self.timeout = Some(budget);
+        self
+    }
+
+    /// Configure the engine to normalize percent-encoding of the uri path
+    /// following RFC 3986 before any rules are evaluated.
+    ///
+    /// Escapes are uppercased (`%2f` becomes `%2F`) and escaped unreserved
+    /// characters are decoded (`%7E` becomes `~`).
+    ///
+    /// Default is false
+    pub fn normalize_percent_encoding(mut self, enabled: bool) -> Self {
+        self.normalize = enabled;
+        self
+    }
+
+    /// Configure the engine to respond with a [`Rewrite::Redirect`] using
+    /// the given status when percent-encoding normalization changed the
+    /// uri, rather than evaluating rules against the normalized uri.
+    ///
+    /// Only applies when [`Engine::normalize_percent_encoding`] is enabled.
+    ///
+    /// Default is None
+    pub fn canonical_redirect(mut self, status: Option<u16>) -> Self {
+        self.canonical_redirect = status;
+        self
+    }
+
+    /// Configure the engine to match rules against the path of full urls
+    /// only, like `mod_rewrite`, so a pattern such as `^/file/(.*)$` also
+    /// applies to `http://localhost/file/a`.
+    ///
+    /// The scheme and authority are stripped before any rules are evaluated
+    /// and reattached to the resulting uri or redirect location. Rules
+    /// substituting an absolute url such as `https://example.com/$1`
+    /// opt out of reattachment and are returned as written.
+    ///
+    /// Default is false
+    pub fn match_path_only(mut self, enabled: bool) -> Self {
+        self.path_only = enabled;
+        self
+    }
+
+    /// Configure the engine to respond with a `403` [`Rewrite::StatusCode`]
+    /// when a [`Condition`] references a variable missing from the [`EngineCtx`]
+    /// rather than treating it as an empty string.
+    ///
+    /// Default is false
+    pub fn fail_closed_on_missing_var(mut self, enabled: bool) -> Self {
+        self.fail_closed = enabled;
+        self
+    }
+
+    /// Configure a fallback [`Rewrite`] returned when no rule was applied
+    /// and no terminal response was produced.
+    ///
+    /// A rule rewriting the uri to itself is still applied, so the default
+    /// is not returned for it.
+    ///
+    /// Avoids relying on a carefully ordered catch-all rule to route
+    /// unmatched requests to a default page or status.
+    pub fn set_default_rewrite(&mut self, rewrite: Rewrite) -> &mut Self {
+        self.default = Some(rewrite);
+        self
+    }
+
+    /// Compare against another engine and report the rules added, removed
+    /// or modified to turn this engine into `other`.
+    ///
+    /// Groups are compared in order, and rules are matched by their text
+    /// representation along with the conditions guarding them.
+    pub fn diff(&self, other: &Engine) -> EngineDiff {
+        diff::diff(&self.groups, &other.groups)
+    }
+
+    /// [`ExprGroup`] instances evaluated in order by the engine.
+    #[inline]
+    pub fn groups(&self) -> &[ExprGroup] {
+        &self.groups
+    }
+
+    /// Report the number of groups, rules and conditions along with the
+    /// memory used by their compiled regexes.
+    pub fn stats(&self) -> EngineStats {
+        self.groups.iter().fold(
+            EngineStats {
+                groups: self.groups.len(),
+                ..Default::default()
+            },
+            |mut stats, g| {
+                stats.rules += g.rules().len();
+                stats.conditions += g.conditions().len();
+                stats.regex_bytes += g.rules().iter().map(|r| r.memory_usage()).sum::<usize>();
+                stats.regex_bytes += g
+                    .conditions()
+                    .iter()
+                    .map(|c| c.memory_usage())
+                    .sum::<usize>();
+                stats
+            },
+        )
+    }
+
+    /// Statically check the configured rules for likely mistakes, such as
+    /// rules made unreachable by an earlier rule within the same group that
+    /// matches every uri and always stops evaluation, or redirect cycles.
+    ///
+    /// Conditions are ignored, so an empty result is not a guarantee that
+    /// every rule is reachable.
+    pub fn validate(&self) -> Vec<Lint> {
+        let mut lints = lint::unreachable_rules(&self.groups);
+        lints.extend(
+            self.detect_redirect_cycles()
+                .into_iter()
+                .map(Lint::RedirectCycle),
+        );
+        lints
+    }
+
+    /// Statically find redirect rules forming obvious cycles across groups,
+    /// such as `/a` redirecting to `/b` which redirects back to `/a`.
+    ///
+    /// Only redirects with literal targets, free of backreferences and
+    /// variables, are followed and conditions are ignored. Each cycle is
+    /// reported as the list of uris visited, ending where it started.
+    pub fn detect_redirect_cycles(&self) -> Vec<Vec<String>> {
+        lint::redirect_cycles(&self.groups)
+    }
+
+    /// Collect the names of all `%{VARIABLE}` references within condition
+    /// test strings and rule substitutions.
+    ///
+    /// Lists the variables an [`EngineCtx`] must supply for the rules
+    /// to evaluate as intended, such as `HTTP_HOST` or `ENV:LANG`.
+    pub fn referenced_variables(&self) -> HashSet<String> {
+        self.groups
+            .iter()
+            .flat_map(|g| {
+                let conditions = g.conditions().iter().flat_map(|c| c.variables());
+                let rules = g.rules().iter().flat_map(|r| r.variables());
+                conditions.chain(rules)
+            })
+            .map(|name| name.to_owned())
+            .collect()
+    }
+
     /// Parse additonal [`Expression`]s to append as [`ExprGroup`]s to the
     /// existing engine.
+    ///
+    /// Each call is parsed on its own, so conditions at the end of one call
+    /// never guard a rule supplied by the next. Use [`RuleParser`] to build
+    /// an engine from rules split across multiple chunks.
     #[inline]
     pub fn add_rules(&mut self, rules: &str) -> Result<&mut Self, ExpressionError> {
         let groups = ExpressionList::from_str(rules)?.groups();
@@ -82,6 +363,76 @@ impl Engine {
         Ok(self)
     }
 
+    /// Read a file of `.htaccess`-style [`Expression`]s to append as
+    /// [`ExprGroup`]s to the existing engine, like [`Engine::add_rules`].
+    ///
+    /// The file must be UTF-8 encoded. Read failures are reported as
+    /// [`ExpressionError::Io`].
+    pub fn add_rules_from_file(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<&mut Self, ExpressionError> {
+        self.add_rules(&std::fs::read_to_string(path)?)
+    }
+
+    /// Build an engine from a UTF-8 encoded file of `.htaccess`-style
+    /// [`Expression`]s, see [`Engine::add_rules_from_file`].
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ExpressionError> {
+        Self::from_str(&std::fs::read_to_string(path)?)
+    }
+
+    /// Register a [`TextMap`] invoked within substitutions and conditions
+    /// using the `${name:key|default}` syntax.
+    ///
+    /// Lookups of missing keys expand to the default, or an empty string
+    /// when no default is given.
+    pub fn add_map(&mut self, name: &str, map: TextMap) -> &mut Self {
+        Arc::make_mut(&mut self.maps).insert(name.to_owned(), Arc::new(map));
+        self
+    }
+
+    /// Append the standard front-controller [`ExprGroup`] used by frameworks
+    /// such as WordPress or Laravel when building the engine.
+    ///
+    /// Requests are routed to `index` unless `%{REQUEST_FILENAME}` resolves
+    /// to an existing file or directory, so the [`EngineCtx`] passed to
+    /// [`Engine::rewrite_ctx`] must supply `DOCUMENT_ROOT`.
+    ///
+    /// The `index` is used as a literal path, failing with
+    /// [`ExpressionError::InvalidFrontController`] if it contains whitespace,
+    /// quotes, `#` or the `$` and `%` references of a substitution.
+    pub fn with_front_controller(mut self, index: &str) -> Result<Self, ExpressionError> {
+        let reserved = |c: char| c.is_whitespace() || "\"'#$%".contains(c);
+        if index.is_empty() || index == "-" || index.starts_with('[') || index.contains(reserved) {
+            return Err(ExpressionError::InvalidFrontController(index.to_owned()));
+        }
+        let group = GroupBuilder::new()
+            .condition(Condition::from_str("%{REQUEST_FILENAME} !-f")?)
+            .condition(Condition::from_str("%{REQUEST_FILENAME} !-d")?)
+            .rule(Rule::from_str(&format!("^ {index} [L]"))?)
+            .build();
+        self.groups.push(group);
+        Ok(self)
+    }
+
+    /// Append an [`ExprGroup`] that permanently redirects paths without
+    /// a file extension to the same path with a trailing slash.
+    ///
+    /// `/dir` redirects to `/dir/` while `/file.txt` is left unchanged.
+    pub fn add_trailing_slash(&mut self) -> &mut Self {
+        self.add_rules(ADD_TRAILING_SLASH)
+            .expect("invalid trailing slash rule")
+    }
+
+    /// Append an [`ExprGroup`] that permanently redirects paths ending
+    /// in a trailing slash to the same path without it.
+    ///
+    /// `/dir/` redirects to `/dir` while `/` is left unchanged.
+    pub fn remove_trailing_slash(&mut self) -> &mut Self {
+        self.add_rules(REMOVE_TRAILING_SLASH)
+            .expect("invalid trailing slash rule")
+    }
+
     /// Evaluate the given URI against the configured [`ExprGroup`] instances
     /// defined and generate a [`Rewrite`] response.
     ///
@@ -96,6 +447,24 @@ impl Engine {
         self.rewrite_ctx(uri, &mut ctx)
     }
 
+    /// Evaluate the given path against the configured [`ExprGroup`] instances
+    /// without requiring an [`EngineCtx`] and generate a [`Rewrite`] response.
+    ///
+    /// This method is only intended for engines without `RewriteCond` rules,
+    /// and debug builds panic when any are present. Release builds evaluate
+    /// conditions against an empty context like [`Engine::rewrite`].
+    ///
+    /// Evaluation is shared with [`Engine::rewrite_str`] using an empty
+    /// context, which does not allocate.
+    pub fn rewrite_path(&self, path: &str) -> Result<Rewrite, EngineError> {
+        debug_assert!(
+            self.groups.iter().all(|g| !g.has_conditions()),
+            "rewrite_path used with an engine containing conditions"
+        );
+        let rewrite = self.rewrite_str(path, &mut EngineCtx::default())?;
+        Ok(rewrite.unwrap_or_else(|| Rewrite::Uri(path.to_owned())))
+    }
+
     /// Evaluate the given URI against the configured [`ExprGroup`] instances
     /// defined and generate a [`Rewrite`] response.
     ///
@@ -105,15 +474,303 @@ impl Engine {
     /// If your engine is using `RewriteCond` rules, you will want to use this
     /// method with a complete `EngineCtx`. See [`Engine::rewrite`] for a simpler
     /// alternative.
-    pub fn rewrite_ctx(&self, uri: &str, ctx: &mut EngineCtx) -> Result<Rewrite, EngineError> {
-        let (mut uri, query) = extra::split_query(uri);
-        for group in self.groups.iter().filter(|g| g.match_conditions(ctx)) {
-            uri = match group.rewrite(&uri)? {
-                Rewrite::Uri(uri) => uri,
-                status => return Ok(status.with_query(query)),
+    ///
+    /// Rule patterns only ever match the path portion of the uri, so anchors
+    /// like `$` are not defeated by a query string, which is re-appended
+    /// to the final result unless replaced by a substitution query
+    /// (see [`ExprGroup::rewrite`]).
+    ///
+    /// An `[L]` rule only stops its own [`ExprGroup`], so later groups are
+    /// still evaluated against the rewritten uri, while an `[END]` rule
+    /// stops every group and produces a [`Rewrite::EndUri`].
+    #[inline]
+    pub fn rewrite_ctx(&self, original: &str, ctx: &mut EngineCtx) -> Result<Rewrite, EngineError> {
+        self.rewrite_outcome(original, ctx)
+            .map(Outcome::into_rewrite)
+    }
+
+    /// Evaluate the path and query of an [`http::Uri`] like
+    /// [`Engine::rewrite_ctx`], producing a [`Rewrite::Uri`] or
+    /// [`Rewrite::EndUri`] that is a valid [`http::Uri`].
+    ///
+    /// Path-only substitutions keep the scheme and authority of the
+    /// original uri, including any userinfo and port, while substitutions
+    /// producing an absolute url replace them. Rewrites that are not valid
+    /// uris fail with [`EngineError::InvalidUri`].
+    #[cfg(feature = "http")]
+    pub fn rewrite_uri(
+        &self,
+        uri: &http::Uri,
+        ctx: &mut EngineCtx,
+    ) -> Result<Rewrite, EngineError> {
+        let path = uri.path_and_query().map_or("/", |p| p.as_str());
+        let rebuild = |new: String| -> Result<String, EngineError> {
+            let new = match (uri.scheme(), uri.authority()) {
+                (Some(scheme), Some(authority)) if !extra::is_absolute_url(&new) => {
+                    format!("{scheme}://{authority}{new}")
+                }
+                _ => new,
+            };
+            new.parse::<http::Uri>()?;
+            Ok(new)
+        };
+        Ok(match self.rewrite_ctx(path, ctx)? {
+            Rewrite::Uri(new) => Rewrite::Uri(rebuild(new)?),
+            Rewrite::EndUri(new) => Rewrite::EndUri(rebuild(new)?),
+            rewrite => rewrite,
+        })
+    }
+
+    /// Evaluate the given URI like [`Engine::rewrite_ctx`], reporting the
+    /// [`TerminationReason`] rule processing stopped for and the number of
+    /// rules applied along with the resulting [`Rewrite`].
+    pub fn rewrite_outcome(
+        &self,
+        original: &str,
+        ctx: &mut EngineCtx,
+    ) -> Result<Outcome, EngineError> {
+        self.outcome(original, ctx, None)
+    }
+
+    /// Evaluate the given URI like [`Engine::rewrite_ctx`], recording the
+    /// groups and rules that matched along with the shifts and responses
+    /// they applied.
+    ///
+    /// The [`Trace`] is returned even when evaluation fails, so the rules
+    /// forming a cycle can be found after [`EngineError::TooManyIterations`].
+    pub fn rewrite_trace(
+        &self,
+        original: &str,
+        ctx: &mut EngineCtx,
+    ) -> (Result<Rewrite, EngineError>, Trace) {
+        let mut trace = Trace::default();
+        let result = self.outcome(original, ctx, Some(&mut trace));
+        (result.map(Outcome::into_rewrite), trace)
+    }
+
+    /// Evaluate the given URI like [`Engine::rewrite_ctx`] without allocating
+    /// a new uri when no rule applies.
+    ///
+    /// Returns `None` when the uri is left unchanged and no default rewrite
+    /// is configured, so callers can keep using the borrowed uri.
+    pub fn rewrite_str(
+        &self,
+        uri: &str,
+        ctx: &mut EngineCtx,
+    ) -> Result<Option<Rewrite>, EngineError> {
+        let (origin, uri) = self.split_origin(uri);
+        let rewrite = match self.evaluate(uri, ctx, None)? {
+            ControlFlow::Break(outcome) => outcome.into_rewrite(),
+            ControlFlow::Continue(Evaluated {
+                normalized: Cow::Borrowed(_),
+                applied: 0,
+                ..
+            }) if self.default.is_none() => return Ok(None),
+            ControlFlow::Continue(eval) => {
+                self.finish(&eval.normalized, eval.uri.into_owned(), eval.applied)
+            }
+        };
+        Ok(Some(rewrite.with_origin(origin)))
+    }
+
+    /// Find the first [`Rule`] matching the given URI without performing
+    /// any rewrite, using the configured [`ExprGroup`] instances as a router.
+    ///
+    /// Groups are checked in order against their [`Condition`] expressions
+    /// using the [`EngineCtx`], and the index and captures of the first rule
+    /// whose pattern matches the uri path are returned.
+    pub fn match_only(&self, uri: &str, ctx: &mut EngineCtx) -> Option<RouteMatch> {
+        ctx.reset();
+        let (path, _) = extra::split_query(&self.normalize(uri));
+        for (index, group) in self.groups.iter().enumerate() {
+            if !group.in_scope(&path) {
+                continue;
+            }
+            ctx.set_uri(&path);
+            if !group.match_conditions(ctx) {
+                continue;
+            }
+            let found = group
+                .rules()
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| r.pass() <= 1)
+                .find_map(|(i, r)| Some((i, r.try_match(&path)?)));
+            if let Some((rule, (captures, named))) = found {
+                return Some(RouteMatch {
+                    group: index,
+                    rule,
+                    captures,
+                    named,
+                });
+            }
+        }
+        None
+    }
+
+    /// Evaluate every group against the uri and produce the final
+    /// [`Outcome`], optionally recording into the [`Trace`].
+    fn outcome(
+        &self,
+        original: &str,
+        ctx: &mut EngineCtx,
+        trace: Option<&mut Trace>,
+    ) -> Result<Outcome, EngineError> {
+        let (origin, original) = self.split_origin(original);
+        let outcome = match self.evaluate(original, ctx, trace)? {
+            ControlFlow::Break(outcome) => outcome,
+            ControlFlow::Continue(eval) => {
+                let rewrite = self.finish(&eval.normalized, eval.uri.into_owned(), eval.applied);
+                Outcome::new(rewrite, eval.last)
+                    .with_applied(eval.applied)
+                    .with_broken_chain(eval.chain_broken)
+            }
+        };
+        Ok(outcome
+            .with_origin(origin)
+            .with_vary(ctx.take_vary())
+            .with_env(ctx.env_vars().clone()))
+    }
+
+    /// Evaluate every group against the uri, breaking with the final
+    /// [`Outcome`] of a response or `[END]` rule, or continuing with the
+    /// [`Evaluated`] state of a non-terminal rewrite.
+    ///
+    /// The uri stays borrowed until a rule applies, and groups without
+    /// any rule matching the current path are skipped.
+    fn evaluate<'u>(
+        &self,
+        original: &'u str,
+        ctx: &mut EngineCtx,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<ControlFlow<Outcome, Evaluated<'u>>, EngineError> {
+        let normalized = self.normalize(original);
+        if let Some(redirect) = self.redirect_canonical(original, &normalized) {
+            return Ok(ControlFlow::Break(Outcome::new(redirect, false)));
+        }
+        ctx.reset();
+        ctx.set_maps((!self.maps.is_empty()).then(|| self.maps.clone()));
+        let deadline = self.timeout.map(|budget| Instant::now() + budget);
+        let mut uri = normalized.clone();
+        let mut last = false;
+        let mut chain_broken = false;
+        let mut applied = 0;
+        for (index, group) in self.groups.iter().enumerate() {
+            if !group.in_scope(&uri) {
+                continue;
+            }
+            ctx.take_missing();
+            ctx.set_uri(&uri);
+            let mark = ctx.vary_len();
+            let matched = group.match_conditions(ctx);
+            if self.fail_closed && ctx.take_missing() {
+                let outcome = Outcome::new(Rewrite::StatusCode(403), false);
+                return Ok(ControlFlow::Break(outcome));
+            }
+            if !matched {
+                ctx.truncate_vary(mark);
+                continue;
+            }
+            // headers read by rule substitutions are not recorded
+            let mark = ctx.vary_len();
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.push(TraceEvent::GroupMatched { group: index });
+            }
+            if !group.may_match(&uri, deadline)? {
+                chain_broken |= group.has_chain();
+                continue;
+            }
+            let outcome = group.rewrite_traced(&uri, ctx, deadline, trace.as_deref_mut())?;
+            ctx.truncate_vary(mark);
+            last |= outcome.termination_reason() == TerminationReason::Last;
+            chain_broken |= outcome.termination_reason() == TerminationReason::Chain;
+            applied += outcome.rules_applied();
+            uri = match outcome.into_rewrite() {
+                Rewrite::Uri(uri) => Cow::Owned(uri),
+                status => {
+                    let outcome = Outcome::new(status, false).with_applied(applied);
+                    return Ok(ControlFlow::Break(outcome));
+                }
             };
         }
-        Ok(Rewrite::Uri(uri).with_query(query))
+        Ok(ControlFlow::Continue(Evaluated {
+            normalized,
+            uri,
+            last,
+            chain_broken,
+            applied,
+        }))
+    }
+
+    /// Split the scheme and authority from the path of a full url when
+    /// matching against the path only.
+    fn split_origin<'u>(&self, uri: &'u str) -> (&'u str, &'u str) {
+        match self.path_only {
+            true => extra::split_origin(uri),
+            false => ("", uri),
+        }
+    }
+
+    /// Normalize percent-encoding of the uri when enabled.
+    fn normalize<'u>(&self, uri: &'u str) -> Cow<'u, str> {
+        match self.normalize {
+            true => extra::normalize_percent_encoding(uri),
+            false => Cow::Borrowed(uri),
+        }
+    }
+
+    /// Produce the configured canonical redirect when normalization
+    /// changed the uri.
+    fn redirect_canonical(&self, original: &str, normalized: &str) -> Option<Rewrite> {
+        match self.canonical_redirect {
+            Some(status) if original != normalized => {
+                Some(Rewrite::Redirect(normalized.to_owned(), status))
+            }
+            _ => None,
+        }
+    }
+
+    /// Produce the final non-terminal [`Rewrite`], substituting the
+    /// configured default when no rule was applied.
+    ///
+    /// A rule rewriting the uri to itself still counts as applied.
+    fn finish(&self, original: &str, uri: String, applied: usize) -> Rewrite {
+        match &self.default {
+            Some(default) if applied == 0 => {
+                let (_, query) = extra::split_query(original);
+                default.clone().with_query(query)
+            }
+            _ => Rewrite::Uri(uri),
+        }
+    }
+}
+
+/// State of a non-terminal rewrite produced by [`Engine::evaluate`].
+struct Evaluated<'u> {
+    /// Uri after percent-encoding normalization.
+    normalized: Cow<'u, str>,
+    /// Uri rewritten by every group.
+    uri: Cow<'u, str>,
+    /// Set once an `[L]` rule stopped a group.
+    last: bool,
+    /// Set once a `[C]` rule failed to match.
+    chain_broken: bool,
+    /// Number of rules applied.
+    applied: usize,
+}
+
+/// Parse and compile every expression without building an [`Engine`],
+/// collecting all errors along with their 1-based line numbers.
+///
+/// Intended as a quick validity check for linters and CI hooks.
+pub fn validate_str(rules: &str) -> Result<(), Vec<(usize, ExpressionError)>> {
+    let errors: Vec<_> = expr::lines(rules)
+        .filter(|(_, line)| !line.is_empty())
+        .filter_map(|(n, line)| Expression::from_str(&line).err().map(|err| (n, err)))
+        .collect();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
     }
 }
 
@@ -121,13 +778,137 @@ impl FromStr for Engine {
     type Err = ExpressionError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let groups = ExpressionList::from_str(s)?.groups();
-        Ok(Self { groups })
+        Ok(Self::from_groups(ExpressionList::from_str(s)?.groups()))
+    }
+}
+
+/// Builder accumulating rules and settings for an [`Engine`].
+///
+/// Unlike the configuration methods of [`Engine`], settings apply to every
+/// group regardless of whether its rules were added before or after them,
+/// and the built engine is never modified afterwards, so it can be shared
+/// behind an [`Arc`].
+///
+/// Per-request data such as the document root or the current date is
+/// supplied through the [`EngineCtx`] passed to [`Engine::rewrite_ctx`].
+///
+/// # Example
+///
+/// ```
+/// use mod_rewrite::EngineBuilder;
+///
+/// let engine = EngineBuilder::new()
+///     .rules("RewriteRule ^/old/(.*) /new/$1 [R=301]")
+///     .expect("failed to process rules")
+///     .max_iterations(5)
+///     .match_path_only(true)
+///     .build();
+/// ```
+#[derive(Debug, Default)]
+pub struct EngineBuilder {
+    engine: Engine,
+    max_iterations: Option<usize>,
+    method_preserving: Option<bool>,
+}
+
+impl EngineBuilder {
+    /// Create an empty [`EngineBuilder`].
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse [`Expression`]s to append as [`ExprGroup`]s, like
+    /// [`Engine::add_rules`].
+    pub fn rules(mut self, rules: &str) -> Result<Self, ExpressionError> {
+        self.engine.add_rules(rules)?;
+        Ok(self)
+    }
+
+    /// Read a UTF-8 encoded file of [`Expression`]s to append as
+    /// [`ExprGroup`]s, like [`Engine::add_rules_from_file`].
+    pub fn rules_from_file(mut self, path: impl AsRef<Path>) -> Result<Self, ExpressionError> {
+        self.engine.add_rules_from_file(path)?;
+        Ok(self)
+    }
+
+    /// Append an already constructed [`ExprGroup`].
+    pub fn group(mut self, group: ExprGroup) -> Self {
+        self.engine.groups.push(group);
+        self
+    }
+
+    /// Register a [`TextMap`], see [`Engine::add_map`].
+    pub fn map(mut self, name: &str, map: TextMap) -> Self {
+        self.engine.add_map(name, map);
+        self
+    }
+
+    /// See [`Engine::max_iterations`].
+    pub fn max_iterations(mut self, iterations: usize) -> Self {
+        self.max_iterations = Some(iterations);
+        self
+    }
+
+    /// See [`Engine::method_preserving_redirects`].
+    pub fn method_preserving_redirects(mut self, enabled: bool) -> Self {
+        self.method_preserving = Some(enabled);
+        self
+    }
+
+    /// See [`Engine::timeout`].
+    pub fn timeout(mut self, budget: Duration) -> Self {
+        self.engine.timeout = Some(budget);
+        self
+    }
+
+    /// See [`Engine::normalize_percent_encoding`].
+    pub fn normalize_percent_encoding(mut self, enabled: bool) -> Self {
+        self.engine.normalize = enabled;
+        self
+    }
+
+    /// See [`Engine::canonical_redirect`].
+    pub fn canonical_redirect(mut self, status: Option<u16>) -> Self {
+        self.engine.canonical_redirect = status;
+        self
+    }
+
+    /// See [`Engine::match_path_only`].
+    pub fn match_path_only(mut self, enabled: bool) -> Self {
+        self.engine.path_only = enabled;
+        self
+    }
+
+    /// See [`Engine::fail_closed_on_missing_var`].
+    pub fn fail_closed_on_missing_var(mut self, enabled: bool) -> Self {
+        self.engine.fail_closed = enabled;
+        self
+    }
+
+    /// See [`Engine::set_default_rewrite`].
+    pub fn default_rewrite(mut self, rewrite: Rewrite) -> Self {
+        self.engine.default = Some(rewrite);
+        self
+    }
+
+    /// Produce the [`Engine`] from all collected rules and settings.
+    pub fn build(self) -> Engine {
+        let mut engine = self.engine;
+        if let Some(iterations) = self.max_iterations {
+            engine = engine.max_iterations(iterations);
+        }
+        if let Some(enabled) = self.method_preserving {
+            engine = engine.method_preserving_redirects(enabled);
+        }
+        engine
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use super::*;
 
     #[test]
@@ -138,7 +919,7 @@ mod tests {
                 r#"
             RewriteRule /static/(.*) /files/$1 [NE,L]
 
-            RewriteRule /(.*)        /index?page=$1
+            RewriteRule /(.*)        /index?page=$1 [QSA]
         "#,
             )
             .unwrap();
@@ -147,28 +928,1320 @@ mod tests {
         assert!(matches!(r, Rewrite::Uri(uri) if uri == "/index?page=files%2F1%2F2"));
 
         let r = engine.rewrite("/1/2/3?a=b").unwrap();
-        println!("{r:?}");
         assert!(matches!(r, Rewrite::Uri(uri) if uri == "/index?page=1%2F2%2F3&a=b"));
     }
 
     #[test]
-    fn test_query() {
+    fn test_rewrite_path() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule /static/(.*) /files/$1 [NE,L]
+
+            RewriteEngine off
+            RewriteRule /(.*) /disabled/$1
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite_path("/static/1/2?a=b").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/files/1/2?a=b"));
+
+        let r = engine.rewrite_path("/other").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/other"));
+
+        // shares evaluation with the other rewrite methods
+        let engine = engine.match_path_only(true);
+        for uri in [
+            "http://localhost/static/a",
+            "http://localhost/other",
+            "/static/b",
+        ] {
+            assert_eq!(
+                engine.rewrite_path(uri).unwrap(),
+                engine.rewrite(uri).unwrap(),
+                "{uri}"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "rewrite_path used with an engine containing conditions")]
+    fn test_rewrite_path_conditions() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{REQUEST_METHOD} =GET
+            RewriteRule /(.*) /get/$1
+        "#,
+        )
+        .unwrap();
+        let _ = engine.rewrite_path("/index.html");
+    }
+
+    #[test]
+    fn test_trailing_slash() {
         let mut engine = Engine::default();
-        engine
-            .add_rules(
-                r#"
-            RewriteRule /static/(.*) /files/$1 [NE,END]
+        engine.add_trailing_slash();
+
+        let r = engine.rewrite("/dir?a=b").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/dir/?a=b"));
+        let r = engine.rewrite("/dir/sub").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/dir/sub/"));
+        let r = engine.rewrite("/dir/").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/dir/"));
+        let r = engine.rewrite("/file.txt").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/file.txt"));
+
+        let mut engine = Engine::default();
+        engine.remove_trailing_slash();
+
+        let r = engine.rewrite("/dir/").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/dir"));
+        let r = engine.rewrite("/").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/"));
+    }
+
+    #[test]
+    fn test_default_rewrite() {
+        let mut engine = Engine::from_str("RewriteRule ^/static/(.*) /files/$1").unwrap();
+        engine.set_default_rewrite(Rewrite::Uri("/404.html".to_owned()));
+
+        let r = engine.rewrite("/static/style.css").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/files/style.css"));
+        let r = engine.rewrite("/missing?a=b").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/404.html?a=b"));
+
+        engine.set_default_rewrite(Rewrite::StatusCode(404));
+        let r = engine.rewrite("/missing").unwrap();
+        assert!(matches!(r, Rewrite::StatusCode(404)));
+
+        let mut engine = Engine::from_str("RewriteRule ^/same$ /same").unwrap();
+        engine.set_default_rewrite(Rewrite::StatusCode(404));
+        let r = engine.rewrite("/same").unwrap();
+        assert_eq!(r, Rewrite::Uri("/same".to_owned()));
+        let r = engine.rewrite_path("/same").unwrap();
+        assert_eq!(r, Rewrite::Uri("/same".to_owned()));
+        let r = engine.rewrite_str("/same", &mut EngineCtx::default());
+        assert_eq!(r.unwrap(), Some(Rewrite::Uri("/same".to_owned())));
+        let r = engine.rewrite("/other").unwrap();
+        assert_eq!(r, Rewrite::StatusCode(404));
+    }
+
+    #[test]
+    fn test_fail_closed() {
+        let rules = r#"
+            RewriteCond %{REQUEST_METHOD} !=GET
+            RewriteRule /(.*) /public/$1
+        "#;
+
+        let engine = Engine::from_str(rules).unwrap();
+        let r = engine.rewrite("/index.html").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/public/index.html"));
+
+        let engine = Engine::from_str(rules)
+            .unwrap()
+            .fail_closed_on_missing_var(true);
+        let r = engine.rewrite("/index.html").unwrap();
+        assert!(matches!(r, Rewrite::StatusCode(403)));
+
+        let req = context::RequestCtx::default().request_method("GET");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        let r = engine.rewrite_ctx("/index.html", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/index.html"));
+    }
+
+    #[test]
+    fn test_vary_headers() {
+        struct Headers;
+        impl context::ContextProvider for Headers {
+            fn fill(&mut self, key: &str) -> Option<&str> {
+                match key {
+                    "HTTP_USER_AGENT" => Some("Mozilla/5.0"),
+                    _ => None,
+                }
+            }
+        }
 
-            RewriteRule /(.*)        /index?page=$1
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_USER_AGENT} >Mozilla
+            RewriteRule /(.*) /browser/$1
+
+            RewriteCond %{REQUEST_METHOD} =GET
+            RewriteRule /(.*) /get/$1
         "#,
-            )
-            .unwrap();
+        )
+        .unwrap();
 
-        let r = engine.rewrite("/static/1/2?a=b").unwrap();
-        assert!(matches!(r, Rewrite::EndUri(uri) if uri == "/files/1/2?a=b"));
+        let mut ctx = EngineCtx::default().with_ctx(Headers);
+        let outcome = engine.rewrite_outcome("/index.html", &mut ctx).unwrap();
+        assert_eq!(
+            outcome.rewrite(),
+            &Rewrite::Uri("/browser/index.html".to_owned())
+        );
+        assert_eq!(outcome.vary_headers(), &["User-Agent".to_owned()]);
 
-        let r = engine.rewrite("/1/2/3?a=b").unwrap();
-        println!("{r:?}");
-        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/index?page=1%2F2%2F3&a=b"));
+        // groups whose conditions failed and substitutions are not recorded
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP:Accept-Language} =fr
+            RewriteRule /(.*) /fr/$1
+
+            RewriteCond %{HTTP_USER_AGENT} >Mozilla
+            RewriteRule /(.*) /$1?ref=%{HTTP_REFERER}
+        "#,
+        )
+        .unwrap();
+        let mut ctx = EngineCtx::default().with_ctx(Headers);
+        let outcome = engine.rewrite_outcome("/index.html", &mut ctx).unwrap();
+        assert_eq!(outcome.vary_headers(), &["User-Agent".to_owned()]);
+    }
+
+    #[test]
+    fn test_path_segment() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{PATH_SEGMENT:2} =b
+            RewriteRule /(.*) /second/$1 [NE,L]
+
+            RewriteCond %{PATH_SEGMENT:5} =
+            RewriteRule /(.*) /short/$1 [NE]
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite("/a/b/c").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/short/second/a/b/c"));
+
+        let r = engine.rewrite("/a/c/b/d/e").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/a/c/b/d/e"));
+    }
+
+    #[test]
+    fn test_query() {
+        let mut engine = Engine::default();
+        engine
+            .add_rules(
+                r#"
+            RewriteRule /static/(.*) /files/$1 [NE,END]
+
+            RewriteRule /(.*)        /index?page=$1 [QSA]
+        "#,
+            )
+            .unwrap();
+
+        let r = engine.rewrite("/static/1/2?a=b").unwrap();
+        assert!(matches!(r, Rewrite::EndUri(uri) if uri == "/files/1/2?a=b"));
+
+        let r = engine.rewrite("/1/2/3?a=b").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/index?page=1%2F2%2F3&a=b"));
+    }
+
+    #[test]
+    fn test_file_test_cache() {
+        use std::cell::Cell;
+
+        use context::{FileInfo, FileResolver, RequestCtx};
+
+        struct Counting<'a>(&'a Cell<usize>);
+
+        impl FileResolver for Counting<'_> {
+            fn stat(&self, path: &str) -> Option<FileInfo> {
+                self.0.set(self.0.get() + 1);
+                (path == "/var/www/a").then(|| FileInfo {
+                    is_file: true,
+                    ..Default::default()
+                })
+            }
+        }
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond /var/www%{REQUEST_URI} -f
+            RewriteRule /(.*) /one/$1 [NE]
+
+            RewriteCond /var/www%{REQUEST_URI} -f
+            RewriteRule /one/(.*) /two/$1 [NE]
+        "#,
+        )
+        .unwrap();
+
+        let calls = Cell::new(0);
+        let mut ctx = EngineCtx::default()
+            .with_ctx(RequestCtx::default().request_uri("/a"))
+            .with_resolver(Counting(&calls));
+
+        let r = engine.rewrite_ctx("/a", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/two/a"));
+        assert_eq!(calls.get(), 1);
+
+        engine.rewrite_ctx("/a", &mut ctx).unwrap();
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_path_end_anchor() {
+        let engine = Engine::from_str(r"RewriteRule ^/([^.]+)$ /$1.html").unwrap();
+
+        let r = engine.rewrite("/about?ref=x").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/about.html?ref=x"));
+
+        let r = engine.rewrite("/about.html?ref=x").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/about.html?ref=x"));
+    }
+
+    #[test]
+    fn test_front_controller() {
+        use context::{FileInfo, FileResolver, ServerCtx};
+
+        struct Site;
+
+        impl FileResolver for Site {
+            fn stat(&self, path: &str) -> Option<FileInfo> {
+                match path {
+                    "/var/www/style.css" => Some(FileInfo {
+                        is_file: true,
+                        ..Default::default()
+                    }),
+                    "/var/www/my file.txt" => Some(FileInfo {
+                        is_file: true,
+                        size: 12,
+                        ..Default::default()
+                    }),
+                    "/var/www/assets" => Some(FileInfo {
+                        is_dir: true,
+                        ..Default::default()
+                    }),
+                    _ => None,
+                }
+            }
+        }
+
+        let engine = Engine::default()
+            .with_front_controller("/index.php")
+            .unwrap();
+        for index in ["", "/a b.php", "/$1.php", "/index.php#x", "\"/index.php\""] {
+            assert!(matches!(
+                Engine::default().with_front_controller(index),
+                Err(ExpressionError::InvalidFrontController(i)) if i == index
+            ));
+        }
+        let rewrite = |uri: &str| {
+            let mut ctx = EngineCtx::default()
+                .with_ctx(ServerCtx::default().document_root("/var/www/"))
+                .with_resolver(Site);
+            engine.rewrite_ctx(uri, &mut ctx).unwrap()
+        };
+
+        assert!(matches!(rewrite("/style.css"), Rewrite::Uri(uri) if uri == "/style.css"));
+        assert!(matches!(rewrite("/assets"), Rewrite::Uri(uri) if uri == "/assets"));
+        assert!(matches!(rewrite("/my%20file.txt"), Rewrite::Uri(uri) if uri == "/my%20file.txt"));
+        assert!(matches!(rewrite("/blog/post"), Rewrite::Uri(uri) if uri == "/index.php"));
+        assert!(matches!(
+            rewrite("/blog/post?page=2"),
+            Rewrite::Uri(uri) if uri == "/index.php?page=2"
+        ));
+
+        // without a document root nothing exists
+        let mut ctx = EngineCtx::default().with_resolver(Site);
+        let r = engine.rewrite_ctx("/style.css", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/index.php"));
+
+        let cond = Condition::from_str("%{REQUEST_FILENAME} -s").unwrap();
+        let mut ctx = EngineCtx::default()
+            .with_ctx(ServerCtx::default().document_root("/var/www"))
+            .with_resolver(Site);
+        ctx.set_uri("/my%20file.txt");
+        assert_eq!(ctx.fill("REQUEST_FILENAME"), "/var/www/my file.txt");
+        assert!(cond.is_met(&mut ctx));
+        ctx.set_uri("/style.css");
+        assert!(!cond.is_met(&mut ctx));
+    }
+
+    #[test]
+    fn test_bucket() {
+        let engine = Engine::from_str(r"RewriteRule ^/(.*) /${bucket:$1:2}/$1").unwrap();
+        for _ in 0..5 {
+            let r = engine.rewrite("/user/42").unwrap();
+            assert_eq!(r, Rewrite::Uri("/1/user/42".to_owned()));
+        }
+        let r = engine.rewrite("/user/7").unwrap();
+        assert_eq!(r, Rewrite::Uri("/0/user/7".to_owned()));
+    }
+
+    #[test]
+    fn test_end() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/old/(.*) /new/$1 [NE,END]
+
+            RewriteRule ^/new/(.*) /newer/$1 [NE]
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite("/old/page").unwrap();
+        assert!(matches!(r, Rewrite::EndUri(uri) if uri == "/new/page"));
+
+        let r = engine.rewrite("/new/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/newer/page"));
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/old/(.*) /new/$1 [NE,L]
+            RewriteRule ^/new/(.*) /skipped/$1 [NE]
+
+            RewriteRule ^/new/(.*) /newer/$1 [NE]
+        "#,
+        )
+        .unwrap();
+        let r = engine.rewrite("/old/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/newer/page"));
+    }
+
+    #[test]
+    fn test_scope() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteScope /api/
+            RewriteCond %{REQUEST_METHOD} =GET
+            RewriteRule ^/api/(.*) /v2/$1 [NE]
+
+            RewriteRule ^/(.*) /site/$1 [NE]
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite("/web/api/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/site/web/api/page"));
+
+        let req = context::RequestCtx::default().request_method("GET");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        let r = engine.rewrite_ctx("/api/users", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/site/v2/users"));
+    }
+
+    #[test]
+    fn test_match_only() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/static/(.*)                  -
+            RewriteRule ^/users/(?P<id>\d+)/(\w+)$     /user.php
+
+            RewriteCond %{REQUEST_METHOD} =POST
+            RewriteRule ^/upload$                      /upload.php
+        "#,
+        )
+        .unwrap();
+
+        let mut ctx = EngineCtx::default();
+        let route = engine
+            .match_only("/users/42/posts?page=2", &mut ctx)
+            .unwrap();
+        assert_eq!((route.group, route.rule), (0, 1));
+        assert_eq!(
+            route.captures,
+            vec![
+                Some("/users/42/posts".to_owned()),
+                Some("42".to_owned()),
+                Some("posts".to_owned()),
+            ]
+        );
+        assert_eq!(route.named.get("id").map(|s| s.as_str()), Some("42"));
+
+        assert!(engine.match_only("/upload", &mut ctx).is_none());
+        let req = context::RequestCtx::default().request_method("POST");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        let route = engine.match_only("/upload", &mut ctx).unwrap();
+        assert_eq!((route.group, route.rule), (1, 0));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let rules: Vec<String> = (0..200)
+            .map(|_| r"RewriteRule ^/([a-z]+)$ /$1".to_owned())
+            .collect();
+        let engine = Engine::from_str(&rules.join("\n"))
+            .unwrap()
+            .max_iterations(rules.len() + 1);
+        let uri = format!("/{}", "a".repeat(4096));
+        let applied = |trace: &Trace| {
+            trace
+                .events
+                .iter()
+                .filter(|e| matches!(e, TraceEvent::RuleMatched { .. }))
+                .count()
+        };
+
+        // warm up the regex caches before timing an unbounded run
+        let (r, trace) = engine.rewrite_trace(&uri, &mut EngineCtx::default());
+        assert!(r.is_ok());
+        assert_eq!(applied(&trace), rules.len());
+        let start = Instant::now();
+        let _ = engine.rewrite_trace(&uri, &mut EngineCtx::default());
+        let elapsed = start.elapsed();
+
+        // half of the unbounded run expires before every rule applied
+        let engine = engine.timeout(elapsed / 2);
+        let (r, trace) = engine.rewrite_trace(&uri, &mut EngineCtx::default());
+        assert!(matches!(r, Err(EngineError::Timeout)));
+        assert!(applied(&trace) < rules.len());
+    }
+
+    #[test]
+    fn test_env() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/fr/(.*)  /$1               [NE,E=LANG:fr]
+            RewriteRule ^/(.*)     /%{ENV:LANG}/$1   [NE]
+
+            RewriteCond %{ENV:LANG} =fr
+            RewriteRule ^/(.*)     /localized/$1     [NE]
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite("/fr/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/localized/fr/page"));
+
+        let r = engine.rewrite("/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "//page"));
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/(\w+)/(.*)$ /$2 [NE,E=SECTION:$1,E=ORIGIN:%{HTTP_HOST}]
+        "#,
+        )
+        .unwrap();
+        let mut ctx =
+            EngineCtx::default().with_ctx(context::RequestCtx::default().http_host("example.com"));
+        let outcome = engine.rewrite_outcome("/docs/intro", &mut ctx).unwrap();
+        assert_eq!(outcome.rewrite(), &Rewrite::Uri("/intro".to_owned()));
+        assert_eq!(
+            outcome.env().get("SECTION").map(String::as_str),
+            Some("docs")
+        );
+        assert_eq!(
+            outcome.env().get("ORIGIN").map(String::as_str),
+            Some("example.com")
+        );
+        assert_eq!(ctx.env_vars(), outcome.env());
+    }
+
+    #[test]
+    fn test_query_append() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/a$ /b?x=1 [QSA]
+            RewriteRule ^/c$ /d     [QSA]
+            RewriteRule ^/e$ /f?x=1
+            RewriteRule ^/g$ /h?
+            RewriteRule ^/r$ /s?x=1 [QSA,R=301]
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite("/a?y=2").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/b?x=1&y=2"));
+        let r = engine.rewrite("/a").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/b?x=1"));
+
+        let r = engine.rewrite("/c?y=2").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/d?y=2"));
+        let r = engine.rewrite("/c").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/d"));
+
+        let r = engine.rewrite("/e?y=2").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/f?x=1"));
+        let r = engine.rewrite("/g?y=2").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/h"));
+
+        let r = engine.rewrite("/r?y=2").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/s?x=1&y=2"));
+    }
+
+    #[test]
+    fn test_validate_str() {
+        assert!(validate_str("RewriteRule ^/a$ /b [L]").is_ok());
+
+        let errors = validate_str(
+            r#"RewriteEngine On
+            RewriteCond %{HTTP_HOST} ^(bad
+            RewriteRule ^/a$ /b
+
+            // RewriteRule ^/( /ignored
+            RewriteRule ^/(.*) /c [X]
+            RewriteBogus /d
+        "#,
+        )
+        .unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|(n, _)| *n).collect();
+        assert_eq!(lines, vec![2, 6, 7]);
+        assert!(matches!(errors[0].1, ExpressionError::ConditionError(_)));
+        assert!(matches!(errors[1].1, ExpressionError::RuleError(_)));
+        assert!(matches!(errors[2].1, ExpressionError::InvalidIdentifier(_)));
+
+        let errors = validate_str(
+            r#"RewriteCond %{HTTP_HOST} \
+                ^www\.example\.com$
+            RewriteRule ^/a$ \
+                /b \
+                [X]
+            RewriteRule ^/c\\
+        "#,
+        )
+        .unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|(n, _)| *n).collect();
+        assert_eq!(lines, vec![3, 6]);
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} \
+                =example.com
+            RewriteRule ^/old/(.*)$ \
+                /new/$1 \
+                [R=301]
+        "#,
+        )
+        .unwrap();
+        let mut ctx =
+            EngineCtx::default().with_ctx(context::RequestCtx::default().http_host("example.com"));
+        let r = engine.rewrite_ctx("/old/page", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/new/page"));
+    }
+
+    #[test]
+    fn test_query_param() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{QUERY:ref} "=news letter"
+            RewriteRule ^/(.*) /campaign/$1 [NE]
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite("/page?ref=news+letter").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/campaign/page?ref=news+letter"));
+
+        let r = engine.rewrite("/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/page"));
+    }
+
+    #[test]
+    fn test_from_groups() {
+        let rules = r#"
+            RewriteCond %{REQUEST_METHOD} =POST
+            RewriteRule ^/form/(.*) /submit/$1 [L]
+
+            RewriteRule ^/old/(.*) /new/$1 [R=301]
+        "#;
+        let engine = Engine::from_groups([
+            GroupBuilder::new()
+                .condition(Condition::from_str("%{REQUEST_METHOD} =POST").unwrap())
+                .rule(Rule::from_str("^/form/(.*) /submit/$1 [L]").unwrap())
+                .build(),
+            GroupBuilder::new()
+                .rule(Rule::from_str("^/old/(.*) /new/$1 [R=301]").unwrap())
+                .build(),
+        ]);
+        assert!(engine.diff(&Engine::from_str(rules).unwrap()).is_empty());
+
+        let r = engine.rewrite("/old/page").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/new/page"));
+
+        let mut ctx =
+            EngineCtx::default().with_ctx(context::RequestCtx::default().request_method("POST"));
+        let r = engine.rewrite_ctx("/form/a", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/submit/a"));
+    }
+
+    #[test]
+    fn test_normalize_percent_encoding() {
+        let engine = Engine::from_str("RewriteRule ^/a%2Fb~$ /found")
+            .unwrap()
+            .normalize_percent_encoding(true);
+        let r = engine.rewrite("/a%2fb%7e?q=%2f").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/found?q=%2f"));
+        let r = engine.rewrite("/x%2fy").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/x%2Fy"));
+
+        let engine = engine.canonical_redirect(Some(301));
+        let r = engine.rewrite("/x%2fy%41").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/x%2FyA"));
+        let r = engine.rewrite("/x%2Fy%").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/x%2Fy%"));
+    }
+
+    #[test]
+    fn test_redirect_keywords() {
+        let rules = r#"
+            RewriteRule ^/old$ /new [R=permanent]
+            RewriteRule ^/tmp$ /new [R=temp]
+            RewriteRule ^/post$ /new [R=seeother]
+            RewriteRule ^/num$ /new [R=301]
+        "#;
+        let classic = Engine::from_str(rules).unwrap();
+        let preserving = Engine::from_str(rules)
+            .unwrap()
+            .method_preserving_redirects(true);
+        for (uri, old, new) in [
+            ("/old", 301, 308),
+            ("/tmp", 302, 307),
+            ("/post", 303, 303),
+            ("/num", 301, 301),
+        ] {
+            let r = classic.rewrite(uri).unwrap();
+            assert!(matches!(r, Rewrite::Redirect(_, status) if status == old));
+            let r = preserving.rewrite(uri).unwrap();
+            assert!(matches!(r, Rewrite::Redirect(_, status) if status == new));
+        }
+    }
+
+    #[test]
+    fn test_referenced_variables() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} =example.com
+            RewriteCond %{REMOTE_ADDR} ^10\.
+            RewriteRule ^/(.*) /internal/$1?host=%{HTTP_HOST}
+
+            RewriteRule ^/static/(.*) /assets/$1 [L]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            engine.referenced_variables(),
+            HashSet::from(["HTTP_HOST".to_owned(), "REMOTE_ADDR".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_text_map() {
+        let path = std::env::temp_dir().join(format!("mod_rewrite-{}-map.txt", std::process::id()));
+        std::fs::write(&path, "alice=100\nbob=200\n").unwrap();
+        let mut engine = Engine::from_str(
+            r#"
+            RewriteCond ${users:%{QUERY:user}} !^$
+            RewriteRule ^/profile$ /users/${users:%{QUERY:user}|0}
+        "#,
+        )
+        .unwrap();
+        engine.add_map(
+            "users",
+            TextMap::with_separator(&path, MapSeparator::Char('=')).unwrap(),
+        );
+
+        let r = engine.rewrite("/profile?user=alice").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/users/100?user=alice"));
+        let r = engine.rewrite("/profile?user=carol").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/profile?user=carol"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/a$ /b [R=301]
+            RewriteRule ^/b$ /a [R=301]
+            RewriteRule ^ - [F]
+            RewriteRule ^/c$ /d
+        "#,
+        )
+        .unwrap();
+        let lints: Vec<String> = engine.validate().iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            lints,
+            vec![
+                "subsequent rule unreachable: rule 3 of group 0 follows terminal rule 2",
+                "redirect cycle: /a -> /b -> /a",
+            ]
+        );
+        assert!(
+            Engine::from_str("RewriteRule ^/a$ /b")
+                .unwrap()
+                .validate()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} ^www\.(.*)$
+            RewriteCond %{REQUEST_METHOD} =GET
+            RewriteRule ^/(.*) https://%1/$1 [R=301]
+
+            RewriteRule ^/a$ /b
+            RewriteRule ^/c$ /d
+        "#,
+        )
+        .unwrap();
+        let stats = engine.stats();
+        assert_eq!(stats.groups, 2);
+        assert_eq!(stats.rules, 3);
+        assert_eq!(stats.conditions, 2);
+        assert!(stats.regex_bytes > 0);
+        assert_eq!(Engine::default().stats(), EngineStats::default());
+    }
+
+    #[test]
+    fn test_expr_const() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond expr false
+            RewriteRule ^/beta/(.*) /new/$1 [L]
+
+            RewriteCond expr "TRUE"
+            RewriteRule ^/(.*) /stable/$1 [NE]
+        "#,
+        )
+        .unwrap();
+        let r = engine.rewrite("/beta/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/stable/beta/page"));
+
+        let flipped = Engine::from_str(
+            r#"
+            RewriteCond expr true [OR]
+            RewriteCond %{HTTP_HOST} =beta.example.com
+            RewriteRule ^/beta/(.*) /new/$1 [L]
+        "#,
+        )
+        .unwrap();
+        let r = flipped.rewrite("/beta/page").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/new/page"));
+
+        assert!(matches!(
+            Engine::from_str("RewriteCond expr \"%{HTTP_HOST} == 'a'\"\nRewriteRule ^ /"),
+            Err(ExpressionError::AtLine { line: 1, source })
+                if matches!(*source, ExpressionError::ConditionError(
+                    error::CondError::UnsupportedExpr(_)
+                ))
+        ));
+    }
+
+    #[test]
+    fn test_termination_reason() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/last$ /a [L]
+            RewriteRule ^/end$ /b [END]
+            RewriteRule ^/redirect$ /c [R]
+            RewriteRule ^/gone$ - [G]
+            RewriteRule ^/(.*)$ /d/$1
+
+            RewriteRule ^/a$ /e
+        "#,
+        )
+        .unwrap();
+        for (uri, reason) in [
+            ("/last", TerminationReason::Last),
+            ("/end", TerminationReason::End),
+            ("/redirect", TerminationReason::Redirect),
+            ("/gone", TerminationReason::Status),
+            ("/other", TerminationReason::FellThrough),
+        ] {
+            let outcome = engine
+                .rewrite_outcome(uri, &mut EngineCtx::default())
+                .unwrap();
+            assert_eq!(outcome.termination_reason(), reason, "{uri}");
+        }
+
+        let outcome = engine
+            .rewrite_outcome("/last", &mut EngineCtx::default())
+            .unwrap();
+        assert!(matches!(outcome.rewrite(), Rewrite::Uri(uri) if uri == "/e"));
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/a/(.*)$ /b/$1 [C]
+            RewriteRule ^/b/x$ /c
+
+            RewriteRule ^/last$ /a/y [L]
+        "#,
+        )
+        .unwrap();
+        for (uri, reason) in [
+            ("/a/x", TerminationReason::FellThrough),
+            ("/a/y", TerminationReason::FellThrough),
+            ("/other", TerminationReason::Chain),
+            ("/last", TerminationReason::Last),
+        ] {
+            let outcome = engine
+                .rewrite_outcome(uri, &mut EngineCtx::default())
+                .unwrap();
+            assert_eq!(outcome.termination_reason(), reason, "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/a/(.*)$ /b/$1 [C,NE]
+            RewriteRule ^/b/x$ /c [chain]
+            RewriteRule ^/c$ /d [L]
+            RewriteRule ^/(.*)$ /end/$1 [NE]
+        "#,
+        )
+        .unwrap();
+
+        let r = engine.rewrite("/a/x").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/d"));
+
+        let r = engine.rewrite("/a/y").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/end/b/y"));
+
+        let r = engine.rewrite("/c").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/end/c"));
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/admin - [C]
+            RewriteRule .* - [F]
+            RewriteRule ^/(.*)$ /public/$1 [NE]
+        "#,
+        )
+        .unwrap();
+        assert!(engine.validate().is_empty());
+        assert!(matches!(
+            engine.rewrite("/admin"),
+            Ok(Rewrite::StatusCode(403))
+        ));
+        let r = engine.rewrite("/home").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/public/home"));
+    }
+
+    #[test]
+    fn test_readme_example() {
+        let mut engine = Engine::default();
+        engine
+            .add_rules(
+                r#"
+            // comments are skipped
+            RewriteRule /file/(.*)     /tmp/$1      [L]
+            RewriteRule /redirect/(.*) /location/$1 [R=302]
+            RewriteRule /blocked/(.*)  -            [F]
+        "#,
+            )
+            .unwrap();
+
+        let r = engine
+            .rewrite("http://localhost/file/my/document.txt")
+            .unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/tmp/my/document.txt"));
+
+        let r = engine.rewrite("/redirect/a/b").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 302) if uri == "/location/a/b"));
+        assert!(matches!(
+            engine.rewrite("/blocked/x"),
+            Ok(Rewrite::StatusCode(403))
+        ));
+    }
+
+    #[test]
+    fn test_force_https() {
+        use context::RequestCtx;
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTPS} off
+            RewriteRule ^/(.*)$ https://%{HTTP_HOST}/$1 [R=301,L]
+        "#,
+        )
+        .unwrap();
+
+        let req = RequestCtx::default().http_host("example.com");
+        let mut ctx = EngineCtx::default().with_ctx(req.clone().https(false));
+        assert_eq!(ctx.fill("HTTPS"), "off");
+        assert_eq!(ctx.fill("REQUEST_SCHEME"), "http");
+        let r = engine.rewrite_ctx("/a/b?x=1", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "https://example.com/a/b?x=1"));
+
+        let mut ctx = EngineCtx::default().with_ctx(req.https(true));
+        assert_eq!(ctx.fill("HTTPS"), "on");
+        assert_eq!(ctx.fill("REQUEST_SCHEME"), "https");
+        let r = engine.rewrite_ctx("/a/b?x=1", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/a/b?x=1"));
+    }
+
+    #[test]
+    fn test_custom_provider() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{GEO_COUNTRY} -in NL,BE
+            RewriteRule ^/(.*)$ /%{GEO_COUNTRY}/$1 [NE]
+        "#,
+        )
+        .unwrap();
+        let vars = HashMap::from([("GEO_COUNTRY".to_owned(), "NL".to_owned())]);
+        let mut ctx = EngineCtx::default()
+            .with_ctx(context::RequestCtx::default())
+            .with_ctx(vars);
+        let r = engine.rewrite_ctx("/page", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/NL/page"));
+
+        let vars = HashMap::from([("GEO_COUNTRY".to_owned(), "US".to_owned())]);
+        let mut ctx = EngineCtx::default().with_ctx(vars);
+        let r = engine.rewrite_ctx("/page", &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/page"));
+    }
+
+    #[test]
+    fn test_rewrite_str() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/old/(.*)$ /new/$1 [L]
+            RewriteRule ^/gone$ - [G]
+        "#,
+        )
+        .unwrap();
+        let mut ctx = EngineCtx::default();
+        assert!(engine.rewrite_str("/page?a=b", &mut ctx).unwrap().is_none());
+        let r = engine.rewrite_str("/old/page?a=b", &mut ctx).unwrap();
+        assert!(matches!(r, Some(Rewrite::Uri(uri)) if uri == "/new/page?a=b"));
+        let r = engine.rewrite_str("/gone", &mut ctx).unwrap();
+        assert!(matches!(r, Some(Rewrite::StatusCode(410))));
+
+        let mut engine = engine;
+        engine.set_default_rewrite(Rewrite::Uri("/index.php".to_owned()));
+        let r = engine.rewrite_str("/page?a=b", &mut ctx).unwrap();
+        assert!(matches!(r, Some(Rewrite::Uri(uri)) if uri == "/index.php?a=b"));
+    }
+
+    #[test]
+    fn test_match_path_only() {
+        let rules = r#"
+            RewriteRule ^/file/(.*)$   /tmp/$1                  [L]
+            RewriteRule ^/old/(.*)$    /new/$1                  [R=301]
+            RewriteRule ^/away/(.*)$   https://example.com/$1   [R=302]
+        "#;
+        let engine = Engine::from_str(rules).unwrap();
+        let r = engine.rewrite("http://localhost/file/a.txt").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "http://localhost/file/a.txt"));
+
+        let engine = engine.match_path_only(true);
+        let r = engine
+            .rewrite("http://localhost:8080/file/a.txt?v=1")
+            .unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "http://localhost:8080/tmp/a.txt?v=1"));
+
+        let r = engine.rewrite("https://localhost/old/page").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "https://localhost/new/page"));
+
+        let r = engine.rewrite("http://localhost/away/page").unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 302) if uri == "https://example.com/page"));
+
+        let r = engine.rewrite("/file/a.txt").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/tmp/a.txt"));
+
+        let mut ctx = EngineCtx::default();
+        let r = engine
+            .rewrite_str("http://localhost/other", &mut ctx)
+            .unwrap();
+        assert!(r.is_none());
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("mod_rewrite-{}-rules", std::process::id()));
+        std::fs::write(&path, "RewriteRule ^/a$ /b [L]\n").unwrap();
+
+        let engine = Engine::from_file(&path).unwrap();
+        assert!(matches!(engine.rewrite("/a"), Ok(Rewrite::Uri(uri)) if uri == "/b"));
+
+        let mut engine = Engine::from_str("RewriteRule ^/b$ /c").unwrap();
+        engine.add_rules_from_file(&path).unwrap();
+        assert_eq!(engine.groups.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+
+        let r = Engine::from_file(&path);
+        assert!(matches!(r, Err(error::ExpressionError::Io(_))));
+    }
+
+    #[test]
+    fn test_introspection() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} =old.example.com
+            RewriteRule ^/(.*) https://example.com/$1 [R=301,L]
+
+            RewriteRule ^/a$ /b
+        "#,
+        )
+        .unwrap();
+
+        let groups = engine.groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].conditions().len(), 1);
+        let rule = &groups[0].rules()[0];
+        assert_eq!(rule.pattern_str(), "^/(.*)");
+        assert_eq!(rule.substitution(), "https://example.com/$1");
+        assert_eq!(
+            rule.flags(),
+            &[
+                RuleFlag::Resolve(RuleResolve::Redirect(301)),
+                RuleFlag::Shift(RuleShift::Last)
+            ]
+        );
+        assert!(groups[1].rules()[0].flags().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use context::RequestCtx;
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} =old.example.com [NC]
+            RewriteRule ^/(.*) https://example.com/$1 [R=301,L]
+
+            RewriteBase /app
+            RewriteRule ^/FILE/(.*)$ files/$1 [NC,E=FILE:$1]
+            RewriteRule ^/gone - [G]
+        "#,
+        )
+        .unwrap()
+        .match_path_only(true);
+
+        let json = serde_json::to_string(&engine).unwrap();
+        let restored: Engine = serde_json::from_str(&json).unwrap();
+        assert!(restored.diff(&engine).is_empty());
+
+        let ctx =
+            || EngineCtx::default().with_ctx(RequestCtx::default().http_host("OLD.example.com"));
+        for uri in ["/page", "http://localhost/file/a.txt", "/gone"] {
+            let expected = engine.rewrite_ctx(uri, &mut ctx()).unwrap();
+            let actual = restored.rewrite_ctx(uri, &mut ctx()).unwrap();
+            assert_eq!(actual, expected, "{uri}");
+        }
+        let r = restored.rewrite("/file/a.txt").unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/app/files/a.txt"));
+
+        let rule: Rule = serde_json::from_str(
+            r#"{"pattern":"^/a$","substitution":"/b","flags":[{"Mod":"NoCase"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(rule.try_rewrite("/A"), Some("/b".to_owned()));
+        assert!(serde_json::from_str::<Rule>(r#"{"pattern":"(","substitution":"/b"}"#).is_err());
+        let exclusive = r#"{"pattern":"^/a$","substitution":"/b",
+            "flags":[{"Resolve":{"Redirect":302}},{"Resolve":{"Status":403}}]}"#;
+        let err = serde_json::from_str::<Rule>(exclusive).unwrap_err();
+        assert!(err.to_string().contains("mutually exclusive"));
+    }
+
+    #[test]
+    fn test_rewrite_trace() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/old/(.*)$ /new/$1 [L]
+
+            RewriteCond %{REQUEST_METHOD} =POST
+            RewriteRule ^/new/(.*)$ - [F]
+        "#,
+        )
+        .unwrap();
+        let mut ctx =
+            EngineCtx::default().with_ctx(context::RequestCtx::default().request_method("POST"));
+        let (r, trace) = engine.rewrite_trace("/old/page", &mut ctx);
+        assert!(matches!(r, Ok(Rewrite::StatusCode(403))));
+        assert_eq!(
+            trace.events,
+            vec![
+                TraceEvent::GroupMatched { group: 0 },
+                TraceEvent::RuleMatched {
+                    rule: 0,
+                    captures: vec![Some("/old/page".to_owned()), Some("page".to_owned())],
+                    uri: "/new/page".to_owned(),
+                },
+                TraceEvent::Shift {
+                    rule: 0,
+                    shift: RuleShift::Last
+                },
+                TraceEvent::GroupMatched { group: 1 },
+                TraceEvent::RuleMatched {
+                    rule: 0,
+                    captures: vec![Some("/new/page".to_owned()), Some("page".to_owned())],
+                    uri: "/new/page".to_owned(),
+                },
+                TraceEvent::Resolved {
+                    rule: 0,
+                    rewrite: Rewrite::StatusCode(403)
+                },
+            ]
+        );
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/a$ /b [N]
+            RewriteRule ^/b$ /a [N]
+        "#,
+        )
+        .unwrap();
+        let (r, trace) = engine.rewrite_trace("/a", &mut EngineCtx::default());
+        assert!(matches!(r, Err(EngineError::TooManyIterations(10))));
+        let cycle: Vec<_> = trace
+            .events
+            .iter()
+            .filter_map(|e| match e {
+                TraceEvent::RuleMatched { rule, uri, .. } => Some((*rule, uri.as_str())),
+                _ => None,
+            })
+            .take(3)
+            .collect();
+        assert_eq!(cycle, vec![(0, "/b"), (1, "/a"), (0, "/b")]);
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_rewrite_uri() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/file/(.*)$  /tmp/$1                  [L]
+            RewriteRule ^/away/(.*)$  https://example.com/$1   [END]
+            RewriteRule ^/bad$        /a<b                     [END]
+            RewriteRule ^/old$        /new                     [R=301]
+        "#,
+        )
+        .unwrap();
+        let mut ctx = EngineCtx::default();
+
+        let uri: http::Uri = "http://user@localhost:8080/file/a.txt?v=1".parse().unwrap();
+        let r = engine.rewrite_uri(&uri, &mut ctx).unwrap();
+        assert!(
+            matches!(r, Rewrite::Uri(uri) if uri == "http://user@localhost:8080/tmp/a.txt?v=1")
+        );
+
+        let uri: http::Uri = "/file/a.txt".parse().unwrap();
+        let r = engine.rewrite_uri(&uri, &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Uri(uri) if uri == "/tmp/a.txt"));
+
+        let uri: http::Uri = "http://localhost/away/page".parse().unwrap();
+        let r = engine.rewrite_uri(&uri, &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::EndUri(uri) if uri == "https://example.com/page"));
+
+        let uri: http::Uri = "/old".parse().unwrap();
+        let r = engine.rewrite_uri(&uri, &mut ctx).unwrap();
+        assert!(matches!(r, Rewrite::Redirect(uri, 301) if uri == "/new"));
+
+        let uri: http::Uri = "/bad".parse().unwrap();
+        let r = engine.rewrite_uri(&uri, &mut ctx);
+        assert!(matches!(r, Err(EngineError::InvalidUri(_))));
+    }
+
+    #[test]
+    fn test_rules_applied() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteRule ^/same$ /same
+            RewriteRule ^/a$    /b
+            RewriteRule ^/b$    /c
+
+            RewriteRule ^/c$    - [G]
+        "#,
+        )
+        .unwrap();
+        let mut ctx = EngineCtx::default();
+
+        let outcome = engine.rewrite_outcome("/same", &mut ctx).unwrap();
+        assert!(matches!(outcome.rewrite(), Rewrite::Uri(uri) if uri == "/same"));
+        assert!(outcome.is_modified());
+        assert_eq!(outcome.rules_applied(), 1);
+
+        let outcome = engine.rewrite_outcome("/other", &mut ctx).unwrap();
+        assert!(!outcome.is_modified());
+        assert_eq!(outcome.rules_applied(), 0);
+
+        let outcome = engine.rewrite_outcome("/a", &mut ctx).unwrap();
+        assert!(matches!(outcome.rewrite(), Rewrite::StatusCode(410)));
+        assert_eq!(outcome.rules_applied(), 3);
+    }
+
+    #[test]
+    fn test_cond_backrefs() {
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{HTTP_HOST} ^(.+)\.example\.com
+            RewriteRule ^/(.*) /sites/%1/$1 [E=SITE:%1]
+        "#,
+        )
+        .unwrap();
+        let req = context::RequestCtx::default().http_host("blog.example.com");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        let r = engine.rewrite_ctx("/posts/1", &mut ctx).unwrap();
+        assert_eq!(r, Rewrite::Uri("/sites/blog/posts/1".to_owned()));
+        assert_eq!(ctx.fill("ENV:SITE"), "blog");
+
+        let req = context::RequestCtx::default().http_host("other.org");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        let r = engine.rewrite_ctx("/posts/1", &mut ctx).unwrap();
+        assert_eq!(r, Rewrite::Uri("/posts/1".to_owned()));
+    }
+
+    #[test]
+    fn test_builder() {
+        let engine = Engine::builder()
+            .max_iterations(2)
+            .method_preserving_redirects(true)
+            .rules("RewriteRule ^/a$ /b\nRewriteRule ^/b$ /a")
+            .unwrap()
+            .rules("RewriteRule ^/old$ /new [R=permanent]")
+            .unwrap()
+            .group(
+                GroupBuilder::new()
+                    .rule(Rule::from_str("^/x$ /y").unwrap())
+                    .build(),
+            )
+            .default_rewrite(Rewrite::StatusCode(404))
+            .build();
+        assert_eq!(engine.groups().len(), 3);
+        assert!(matches!(
+            engine.rewrite("/a"),
+            Err(EngineError::TooManyIterations(2))
+        ));
+        assert_eq!(
+            engine.rewrite("/old").unwrap(),
+            Rewrite::Redirect("/new".to_owned(), 308)
+        );
+        assert_eq!(engine.rewrite("/x").unwrap(), Rewrite::Uri("/y".to_owned()));
+        assert_eq!(engine.rewrite("/z").unwrap(), Rewrite::StatusCode(404));
+
+        let engine = Engine::builder().build();
+        assert_eq!(engine.stats(), Engine::default().stats());
+    }
+
+    #[test]
+    fn test_error_line() {
+        let rules = "
+            RewriteRule ^/a$ /b
+
+            RewriteCond %{HTTP_HOST} =example.com
+            RewriteRule ^/c$ /d [L,BOGUS]
+        ";
+        let err = Engine::from_str(rules).unwrap_err();
+        assert_eq!(err.line(), Some(5));
+        assert_eq!(err.to_string(), "line 5: Invalid flag in rule definition");
+        assert!(matches!(
+            err,
+            ExpressionError::AtLine { source, .. }
+                if matches!(*source, ExpressionError::RuleError(error::RuleError::InvalidFlag(_)))
+        ));
+
+        let mut engine = Engine::default();
+        let err = engine
+            .add_rules("RewriteRule ^/a$ /b\nRewrite ^/b")
+            .unwrap_err();
+        assert_eq!(err.line(), Some(2));
+        assert!(
+            Engine::from_file("/nonexistent/rules")
+                .unwrap_err()
+                .line()
+                .is_none()
+        );
     }
 }
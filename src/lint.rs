@@ -0,0 +1,215 @@
+//! Static analysis of the rules configured within an [`Engine`](super::Engine).
+//!
+//! Checks are best-effort and ignore [`Condition`](super::Condition)
+//! expressions, so they are intended for validation tools rather
+//! than as a guarantee of runtime behavior.
+
+use std::fmt;
+
+use super::expr::ExprGroup;
+use super::extra;
+use super::rule::{Rule, RuleResolve, RuleShift};
+
+/// Sample paths a pattern must match to be considered unconditional.
+const PROBES: &[&str] = &["", "/", "/index.html", "/a/b/c", "/a b/%20?"];
+
+/// Likely configuration mistake reported by [`Engine::validate`](super::Engine::validate).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lint {
+    /// Rule that can never be reached because an earlier rule within the
+    /// same group matches every uri and always stops evaluation.
+    UnreachableRule {
+        group: usize,
+        rule: usize,
+        shadowed_by: usize,
+    },
+    /// Redirect chain with literal targets that leads back to itself,
+    /// see [`Engine::detect_redirect_cycles`](super::Engine::detect_redirect_cycles).
+    RedirectCycle(Vec<String>),
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnreachableRule {
+                group,
+                rule,
+                shadowed_by,
+            } => write!(
+                f,
+                "subsequent rule unreachable: rule {rule} of group {group} follows terminal rule {shadowed_by}"
+            ),
+            Self::RedirectCycle(uris) => write!(f, "redirect cycle: {}", uris.join(" -> ")),
+        }
+    }
+}
+
+/// Returns true if the rule matches every uri and always stops evaluation
+/// of the group by a response, `[L]` or `[END]`.
+fn is_unconditional_terminal(rule: &Rule) -> bool {
+    let terminal =
+        rule.resolve().is_some() || matches!(rule.shift(), Some(RuleShift::Last | RuleShift::End));
+    terminal && rule.pass() <= 1 && PROBES.iter().all(|p| rule.try_match(p).is_some())
+}
+
+/// Find rules following an unconditional terminal rule within the
+/// same enabled group.
+pub(crate) fn unreachable_rules(groups: &[ExprGroup]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    for (group, g) in groups.iter().enumerate().filter(|(_, g)| g.is_enabled()) {
+        // members of a chain only apply when the previous member matched
+        let Some(shadowed_by) = g.rules().iter().enumerate().position(|(i, r)| {
+            is_unconditional_terminal(r) && (i == 0 || !g.rules()[i - 1].is_chained())
+        }) else {
+            continue;
+        };
+        lints.extend(
+            (shadowed_by + 1..g.rules().len()).map(|rule| Lint::UnreachableRule {
+                group,
+                rule,
+                shadowed_by,
+            }),
+        );
+    }
+    lints
+}
+
+/// Retrieve the path of a redirect substitution without any
+/// backreferences, variables or function calls.
+fn literal_target(rule: &Rule) -> Option<&str> {
+    if !rule.resolve().is_some_and(RuleResolve::is_redirect) {
+        return None;
+    }
+    let target = rule.substitution();
+    if target.contains(['$', '%']) || extra::is_absolute_url(target) {
+        return None;
+    }
+    target.split('?').next()
+}
+
+/// Find redirect chains with literal targets that lead back to a uri
+/// already visited.
+///
+/// Each uri is redirected by the first matching redirect rule across all
+/// enabled groups, and every cycle is reported once starting and ending
+/// with its lowest uri.
+pub(crate) fn redirect_cycles(groups: &[ExprGroup]) -> Vec<Vec<String>> {
+    let rules: Vec<&Rule> = groups
+        .iter()
+        .filter(|g| g.is_enabled())
+        .flat_map(|g| g.rules())
+        .filter(|r| r.resolve().is_some_and(RuleResolve::is_redirect))
+        .collect();
+    let next = |uri: &str| {
+        rules
+            .iter()
+            .find(|r| r.try_match(uri).is_some())
+            .and_then(|r| literal_target(r))
+    };
+
+    let mut cycles = Vec::new();
+    for start in rules.iter().filter_map(|r| literal_target(r)) {
+        let mut chain = vec![start];
+        while let Some(target) = chain.last().and_then(|uri| next(uri)) {
+            let Some(pos) = chain.iter().position(|uri| *uri == target) else {
+                chain.push(target);
+                continue;
+            };
+            let mut cycle: Vec<String> = chain[pos..].iter().map(|s| s.to_string()).collect();
+            let lowest = (0..cycle.len()).min_by_key(|i| &cycle[*i]).unwrap_or(0);
+            cycle.rotate_left(lowest);
+            cycle.push(cycle[0].clone());
+            if !cycles.contains(&cycle) {
+                cycles.push(cycle);
+            }
+            break;
+        }
+    }
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::expr::ExpressionList;
+
+    #[test]
+    fn test_unreachable_rules() {
+        let groups = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/admin - [F]
+            RewriteRule .* - [F]
+            RewriteRule ^/a$ /b
+            RewriteRule ^/c$ /d [R=302]
+
+            RewriteCond %{HTTP_HOST} =old.example.com
+            RewriteRule ^ https://example.com/ [R=301]
+            RewriteRule ^/e$ /f
+
+            RewriteRule ^/.* /index.php [pass=2,L]
+            RewriteRule ^$ /empty [L]
+            RewriteRule ^/g$ /h
+        "#,
+        )
+        .unwrap()
+        .groups();
+
+        let lints = unreachable_rules(&groups);
+        assert_eq!(
+            lints,
+            vec![
+                Lint::UnreachableRule {
+                    group: 0,
+                    rule: 2,
+                    shadowed_by: 1
+                },
+                Lint::UnreachableRule {
+                    group: 0,
+                    rule: 3,
+                    shadowed_by: 1
+                },
+                Lint::UnreachableRule {
+                    group: 1,
+                    rule: 1,
+                    shadowed_by: 0
+                },
+            ]
+        );
+        assert!(
+            lints[0]
+                .to_string()
+                .starts_with("subsequent rule unreachable")
+        );
+    }
+
+    #[test]
+    fn test_redirect_cycles() {
+        let groups = ExpressionList::from_str(
+            r#"
+            RewriteRule ^/a$ /b [R=301]
+            RewriteRule ^/c$ /d [R=301]
+
+            RewriteRule ^/b$ /a?from=b [R=302]
+            RewriteRule ^/d/(.*) /$1 [R=302]
+            RewriteRule ^/self$ /self [R]
+        "#,
+        )
+        .unwrap()
+        .groups();
+
+        assert_eq!(
+            redirect_cycles(&groups),
+            vec![
+                vec!["/a".to_owned(), "/b".to_owned(), "/a".to_owned()],
+                vec!["/self".to_owned(), "/self".to_owned()],
+            ]
+        );
+
+        let groups = ExpressionList::from_str("RewriteRule ^/a$ /b [R=301]")
+            .unwrap()
+            .groups();
+        assert!(redirect_cycles(&groups).is_empty());
+    }
+}
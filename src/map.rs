@@ -0,0 +1,395 @@
+//! Internal `RewriteMap` functions used within `RewriteRule` substitutions.
+//!
+//! Designed as a subset of [official](https://httpd.apache.org/docs/current/mod/mod_rewrite.html#rewritemap)
+//! `int:` map functions invoked with the `${function:argument}` syntax.
+//!
+//! Also provides the non-standard `${bucket:key:N}` function which
+//! deterministically hashes `key` into a bucket within `0..N` for
+//! traffic splitting.
+//!
+//! `txt:` maps are supported as [`TextMap`] instances registered with
+//! [`Engine::add_map`](super::Engine::add_map) and invoked with the
+//! `${name:key|default}` syntax.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::RwLock,
+    time::SystemTime,
+};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+
+use super::conditions::EngineCtx;
+use super::rule::ESCAPE;
+
+/// Registered [`TextMap`] instances by name.
+pub(crate) type Maps = HashMap<String, std::sync::Arc<TextMap>>;
+
+/// Separator between the key and value of each [`TextMap`] line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum MapSeparator {
+    /// Any run of whitespace like Apache `txt:` maps.
+    #[default]
+    Whitespace,
+    /// A single character such as `\t` or `=`.
+    Char(char),
+}
+
+impl MapSeparator {
+    /// Split a line into its trimmed key and value.
+    ///
+    /// Whitespace separated values end at the next whitespace like Apache,
+    /// ignoring any trailing words.
+    fn split<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str)> {
+        match self {
+            Self::Whitespace => {
+                let mut words = line.split_whitespace();
+                Some((words.next()?, words.next()?))
+            }
+            Self::Char(c) => {
+                let (key, value) = line.split_once(*c)?;
+                Some((key.trim(), value.trim()))
+            }
+        }
+    }
+}
+
+/// `RewriteMap` loaded from a text file of key/value lines.
+///
+/// Blank lines and lines starting with `#` are ignored, while lines
+/// missing the [`MapSeparator`] are skipped with a logged warning.
+#[derive(Debug)]
+pub struct TextMap {
+    path: PathBuf,
+    separator: MapSeparator,
+    reload: bool,
+    state: RwLock<MapState>,
+}
+
+/// Entries of a [`TextMap`] along with the modification time
+/// of the file they were loaded from.
+#[derive(Debug)]
+struct MapState {
+    modified: Option<SystemTime>,
+    entries: HashMap<String, String>,
+}
+
+impl TextMap {
+    /// Load a map from a file using whitespace separated lines.
+    #[inline]
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_separator(path, MapSeparator::Whitespace)
+    }
+
+    /// Load a map from a file using the specified [`MapSeparator`].
+    pub fn with_separator(path: impl AsRef<Path>, separator: MapSeparator) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let entries = parse(&path, &fs::read_to_string(&path)?, &separator);
+        Ok(Self {
+            path,
+            separator,
+            reload: false,
+            state: RwLock::new(MapState { modified, entries }),
+        })
+    }
+
+    /// Configure the map to re-stat its file on every lookup and reload
+    /// the entries whenever the modification time changed.
+    ///
+    /// Failed reloads are logged once and keep the previous entries until
+    /// the modification time changes again, such as when a deleted file
+    /// is restored.
+    ///
+    /// Default is false
+    pub fn reload_on_change(mut self, enabled: bool) -> Self {
+        self.reload = enabled;
+        self
+    }
+
+    /// Retrieve the value associated with the key.
+    pub fn get(&self, key: &str) -> Option<String> {
+        if self.reload {
+            self.refresh();
+        }
+        let state = self.state.read().unwrap_or_else(|err| err.into_inner());
+        state.entries.get(key).cloned()
+    }
+
+    /// Reload the entries if the modification time of the file changed.
+    ///
+    /// Lookups only share the read lock while the file is unchanged, the
+    /// write lock is taken once a new modification time is seen and the
+    /// time is compared again in case another thread already reloaded.
+    fn refresh(&self) {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let state = self.state.read().unwrap_or_else(|err| err.into_inner());
+        if modified == state.modified {
+            return;
+        }
+        drop(state);
+        let mut state = self.state.write().unwrap_or_else(|err| err.into_inner());
+        if modified == state.modified {
+            return;
+        }
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => state.entries = parse(&self.path, &contents, &self.separator),
+            Err(err) => log::warn!("failed to reload rewrite map {:?}: {err}", self.path),
+        }
+        // also record failed stats so a missing file is not retried on every lookup
+        state.modified = modified;
+    }
+}
+
+/// Parse the contents of a [`TextMap`] file.
+fn parse(path: &Path, contents: &str, separator: &MapSeparator) -> HashMap<String, String> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|(n, line)| match separator.split(line) {
+            Some((key, value)) if !key.is_empty() => Some((key.to_owned(), value.to_owned())),
+            _ => {
+                log::warn!("skipping malformed line {n} in rewrite map {path:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Supported internal map functions.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum MapFunc {
+    ToLower,
+    ToUpper,
+    Escape,
+    Unescape,
+    Bucket,
+}
+
+/// Stable 64-bit FNV-1a hash, independent of process or platform.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Hash `key:N` into a bucket within `0..N`.
+///
+/// Returns an empty string if `N` is missing, invalid or zero.
+fn bucket(arg: &str) -> String {
+    arg.rsplit_once(':')
+        .and_then(|(key, n)| Some((key, n.trim().parse::<u64>().ok()?)))
+        .filter(|(_, n)| *n > 0)
+        .map(|(key, n)| (fnv1a(key) % n).to_string())
+        .unwrap_or_default()
+}
+
+impl MapFunc {
+    /// Apply the function to the already expanded argument.
+    pub fn apply(&self, arg: &str) -> String {
+        match self {
+            Self::ToLower => arg.to_lowercase(),
+            Self::ToUpper => arg.to_uppercase(),
+            Self::Escape => utf8_percent_encode(arg, ESCAPE).to_string(),
+            Self::Unescape => percent_decode_str(arg).decode_utf8_lossy().into_owned(),
+            Self::Bucket => bucket(arg),
+        }
+    }
+}
+
+impl FromStr for MapFunc {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tolower" => Ok(Self::ToLower),
+            "toupper" => Ok(Self::ToUpper),
+            "escape" => Ok(Self::Escape),
+            "unescape" => Ok(Self::Unescape),
+            "bucket" => Ok(Self::Bucket),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Section of a substitution string split around map function calls.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Segment<'a> {
+    Text(&'a str),
+    Call(MapFunc, &'a str),
+    /// Lookup of `key|default` within the named [`TextMap`].
+    Lookup(&'a str, &'a str),
+}
+
+/// Find the index of the brace closing a `${` opened just before `start`.
+fn closing_brace(s: &str, start: usize) -> Option<usize> {
+    let mut depth = 1;
+    for (i, c) in s[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => continue,
+        }
+        if depth == 0 {
+            return Some(start + i);
+        }
+    }
+    None
+}
+
+/// Split a substitution string into plain text and `${function:argument}`
+/// calls for known [`MapFunc`] names or maps accepted by `is_map`.
+///
+/// Unknown names (such as `${name}` capture references) are left as text.
+pub(crate) fn segments(s: &str, is_map: impl Fn(&str) -> bool) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut text = 0;
+    let mut index = 0;
+    while let Some(offset) = s[index..].find("${") {
+        let open = index + offset;
+        let start = open + 2;
+        index = start;
+        let Some(end) = closing_brace(s, start) else {
+            break;
+        };
+        let Some((name, arg)) = s[start..end].split_once(':') else {
+            continue;
+        };
+        let segment = match MapFunc::from_str(name) {
+            Ok(func) => Segment::Call(func, arg),
+            Err(_) if is_map(name) => Segment::Lookup(name, arg),
+            Err(_) => continue,
+        };
+        if text < open {
+            segments.push(Segment::Text(&s[text..open]));
+        }
+        segments.push(segment);
+        index = end + 1;
+        text = index;
+    }
+    if text < s.len() {
+        segments.push(Segment::Text(&s[text..]));
+    }
+    segments
+}
+
+/// Evaluate every map function call and map lookup within an already
+/// expanded string.
+pub(crate) fn expand(s: &str, ctx: &EngineCtx) -> String {
+    segments(s, |name| ctx.has_map(name))
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.to_owned(),
+            Segment::Call(func, arg) => func.apply(arg),
+            Segment::Lookup(name, arg) => ctx.lookup(name, arg),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_segments() {
+        assert_eq!(
+            segments("/a/${tolower:$1}/${name}/${toupper:${x}}", |_| false),
+            vec![
+                Segment::Text("/a/"),
+                Segment::Call(MapFunc::ToLower, "$1"),
+                Segment::Text("/${name}/"),
+                Segment::Call(MapFunc::ToUpper, "${x}"),
+            ]
+        );
+        assert_eq!(
+            segments("/${tolower:$1", |_| false),
+            vec![Segment::Text("/${tolower:$1")]
+        );
+        assert_eq!(
+            segments("/${users:$1|nobody}/${other:x}", |name| name == "users"),
+            vec![
+                Segment::Text("/"),
+                Segment::Lookup("users", "$1|nobody"),
+                Segment::Text("/${other:x}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bucket() {
+        let first = MapFunc::Bucket.apply("127.0.0.1:100");
+        let n: u64 = first.parse().unwrap();
+        assert!(n < 100);
+        for _ in 0..10 {
+            assert_eq!(MapFunc::Bucket.apply("127.0.0.1:100"), first);
+        }
+        assert_eq!(bucket("key:1"), "0");
+        assert_eq!(bucket("key:0"), "");
+        assert_eq!(bucket("key"), "");
+        assert_eq!(
+            expand("/b/${bucket:a:b:1}/", &EngineCtx::default()),
+            "/b/0/"
+        );
+    }
+
+    fn write_map(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mod_rewrite-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_text_map() {
+        let path = write_map("spaces.txt", "# users\nalice   1\n\nbob 2 extra\nbroken\n");
+        let map = TextMap::open(&path).unwrap();
+        assert_eq!(map.get("alice"), Some("1".to_owned()));
+        assert_eq!(map.get("bob"), Some("2".to_owned()));
+        assert_eq!(map.get("broken"), None);
+        fs::remove_file(&path).unwrap();
+
+        let path = write_map("tabs.txt", "new york\tny\nlos angeles\tla\n");
+        let map = TextMap::with_separator(&path, MapSeparator::Char('\t')).unwrap();
+        assert_eq!(map.get("new york"), Some("ny".to_owned()));
+        assert_eq!(map.get("los angeles"), Some("la".to_owned()));
+        fs::remove_file(&path).unwrap();
+
+        let path = write_map("equals.txt", "a = b=c\nmissing\n=empty\n");
+        let map = TextMap::with_separator(&path, MapSeparator::Char('=')).unwrap();
+        assert_eq!(map.get("a"), Some("b=c".to_owned()));
+        assert_eq!(map.state.read().unwrap().entries.len(), 1);
+        fs::remove_file(&path).unwrap();
+
+        assert!(TextMap::open(std::env::temp_dir().join("mod_rewrite-missing")).is_err());
+    }
+
+    #[test]
+    fn test_reload_on_change() {
+        let path = write_map("reload.txt", "key old\n");
+        let touch = |offset| {
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(offset))
+                .unwrap();
+        };
+        touch(1);
+        let cached = TextMap::open(&path).unwrap();
+        let map = TextMap::open(&path).unwrap().reload_on_change(true);
+        assert_eq!(map.get("key"), Some("old".to_owned()));
+
+        fs::write(&path, "key new\n").unwrap();
+        touch(2);
+        assert_eq!(map.get("key"), Some("new".to_owned()));
+        assert_eq!(cached.get("key"), Some("old".to_owned()));
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(map.get("key"), Some("new".to_owned()));
+        assert_eq!(map.state.read().unwrap().modified, None);
+        assert_eq!(map.get("key"), Some("new".to_owned()));
+    }
+}
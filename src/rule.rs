@@ -1,16 +1,20 @@
-use std::str::FromStr;
+use std::{collections::HashMap, fmt, str::FromStr};
 
-use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use percent_encoding::{AsciiSet, CONTROLS, NON_ALPHANUMERIC, utf8_percent_encode};
 use regex_automata::{
-    MatchKind,
+    MatchKind, PatternID,
     meta::{self, Regex},
     util,
 };
 
+use super::conditions::parse::{quote, tokenize};
+use super::conditions::{EngineCtx, context};
 use super::error::RuleError;
+use super::extra::ParseMode;
+use super::map::{self, Segment};
 
 // https://url.spec.whatwg.org/#percent-encoded-bytes
-const ESCAPE: &AsciiSet = &CONTROLS
+pub(crate) const ESCAPE: &AsciiSet = &CONTROLS
     .add(b'~')
     .add(b' ') // fragment encoding
     .add(b'\'')
@@ -35,6 +39,32 @@ const ESCAPE: &AsciiSet = &CONTROLS
     .add(b'+')
     .add(b',');
 
+/// [`ESCAPE`] applied to backreferences inserted before the query string.
+const PATH_ESCAPE: &AsciiSet = &ESCAPE.remove(b'/');
+
+// https://datatracker.ietf.org/doc/html/rfc3986#section-2.3
+const BACKREF_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Escaping applied to backreference values expanded in the substitution.
+#[derive(Clone, Copy)]
+enum Escape {
+    /// Insert values as-is, used by `[NE]` and function arguments.
+    None,
+    /// Encode characters unsafe within a uri, keeping existing escapes.
+    Default,
+    /// [`Escape::Default`] keeping `/`, used before the query string.
+    Path,
+    /// Encode every reserved character, used by `[B]`.
+    Backrefs,
+}
+
+/// Positional and named captures produced by [`Rule::try_match`].
+pub(crate) type Captures = (Vec<Option<String>>, HashMap<String, String>);
+
 /// Singular `RewriteRule` expression definition.
 ///
 /// It contains a regex pattern to match against a request uri,
@@ -44,47 +74,317 @@ const ESCAPE: &AsciiSet = &CONTROLS
 /// Supports a subset of [offical](https://httpd.apache.org/docs/current/mod/mod_rewrite.html#rewriterule)
 /// mod_rewrite rules.
 #[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "RuleRepr", try_from = "RuleRepr")
+)]
 pub struct Rule {
+    source: String,
+    anchored: bool,
+    effective: String,
     pattern: Regex,
     rewrite: String,
     flags: Vec<RuleFlag>,
+    unknown: Vec<String>,
 }
 
 impl Rule {
+    /// Parse a rule expression, skipping unknown flags rather than
+    /// failing when `mode` is [`ParseMode::Lenient`].
+    ///
+    /// Patterns and substitutions containing whitespace are wrapped in
+    /// `"..."` or `'...'` quotes, where `\"` or `\'` escapes the quote.
+    pub fn parse(s: &str, mode: ParseMode) -> Result<Self, RuleError> {
+        let mut items = tokenize(s, RuleError::UnclosedQuotation)?.into_iter();
+        let pattern = items.next().ok_or(RuleError::MissingPattern)?;
+        let rewrite = items.next().ok_or(RuleError::MissingRewrite)?;
+        if rewrite.starts_with('[') && rewrite.ends_with(']') {
+            return Err(RuleError::MissingRewrite);
+        }
+        let (flags, unknown) = match items.next() {
+            Some(flags) => parse_flags(&flags, mode)?,
+            None => (Vec::new(), Vec::new()),
+        };
+        if let Some(next) = items.next() {
+            return Err(RuleError::InvalidSuffix(next));
+        }
+
+        let (effective, regex) = compile(&pattern, &flags, false)?;
+        Ok(Self {
+            source: pattern,
+            anchored: false,
+            effective,
+            pattern: regex,
+            rewrite,
+            flags,
+            unknown,
+        })
+    }
+
+    /// Build a rule from a path template rather than a regex pattern.
+    ///
+    /// Each `:name` segment of the template matches a single non-empty
+    /// path segment exposed as the named backreference `${name}`, and
+    /// `:name` placeholders within the rewrite are substituted with it.
+    /// The rest of the template must match literally and in full.
+    ///
+    /// ```
+    /// use mod_rewrite::Rule;
+    ///
+    /// let rule = Rule::from_template("/users/:id", "/profile?id=:id", "[L]").unwrap();
+    /// assert_eq!(rule.try_rewrite("/users/42"), Some("/profile?id=42".to_owned()));
+    /// ```
+    pub fn from_template(template: &str, rewrite: &str, flags: &str) -> Result<Self, RuleError> {
+        let mut pattern = String::from("^");
+        let mut names = Vec::new();
+        for (i, segment) in template.split('/').enumerate() {
+            if i > 0 {
+                pattern.push('/');
+            }
+            match segment.strip_prefix(':').filter(|name| is_name(name)) {
+                Some(name) => {
+                    pattern.push_str(&format!("(?P<{name}>[^/]+)"));
+                    names.push(name);
+                }
+                None => pattern.extend(segment.chars().flat_map(escape_char)),
+            }
+        }
+        pattern.push('$');
+
+        if rewrite.is_empty() {
+            return Err(RuleError::MissingRewrite);
+        }
+        let (flags, unknown) = match flags.trim() {
+            "" => (Vec::new(), Vec::new()),
+            flags => parse_flags(flags, ParseMode::Strict)?,
+        };
+        let (effective, regex) = compile(&pattern, &flags, false)?;
+        Ok(Self {
+            source: pattern,
+            anchored: false,
+            effective,
+            pattern: regex,
+            rewrite: substitute_names(rewrite, &names),
+            flags,
+            unknown,
+        })
+    }
+
+    /// Retrieve the regex pattern as written.
+    #[inline]
+    pub fn pattern_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Retrieve the parsed [`RuleFlag`]s in the order they were declared.
+    #[inline]
+    pub fn flags(&self) -> &[RuleFlag] {
+        &self.flags
+    }
+
+    /// Retrieve the unknown flags skipped while parsing in
+    /// [`ParseMode::Lenient`].
+    #[inline]
+    pub fn unknown_flags(&self) -> &[String] {
+        &self.unknown
+    }
+
+    /// Require the pattern to match from the start of the uri by
+    /// prepending an implicit `^` unless the pattern is already anchored.
+    pub fn anchored(mut self) -> Self {
+        if !self.source.starts_with('^') {
+            let (effective, pattern) = compile(&self.source, &self.flags, true)
+                .expect("anchoring a valid pattern is always valid");
+            self.effective = effective;
+            self.pattern = pattern;
+            self.anchored = true;
+        }
+        self
+    }
+
+    /// Retrieve the source of the regex actually compiled for the rule,
+    /// including any anchoring or case-insensitivity applied to the
+    /// pattern as written.
+    #[inline]
+    pub fn effective_pattern(&self) -> String {
+        self.effective.clone()
+    }
+
     /// Try to match the rewrite expression pattern to the specified uri.
     ///
     /// Produces a new re-written string if the rewrite rule matched.
+    ///
+    /// Values inserted by backreferences are percent-encoded (including
+    /// `?`, `#`, space and stray `%`, along with `/` once within the query
+    /// string) unless the rule has the `[NE]` flag, while the `[B]` flag
+    /// encodes every reserved character. The literal text of the
+    /// substitution is kept as written.
+    ///
+    /// Backreferences are expanded before any `${function:argument}` call
+    /// is applied, and are inserted into function arguments unescaped.
+    ///
+    /// Named groups such as `(?P<user>\w+)` are referenced with `${user}`
+    /// alongside numbered `$N` references and escaped the same way, while
+    /// a name not defined by the pattern expands to an empty string.
+    ///
+    /// A substitution of exactly `-` leaves the uri unchanged, so the
+    /// matched uri is produced as-is while the flags still apply.
     #[inline]
     pub fn try_rewrite(&self, uri: &str) -> Option<String> {
+        self.try_rewrite_ctx(uri, &mut EngineCtx::default())
+    }
+
+    /// Try to match the rewrite expression pattern to the specified uri
+    /// like [`Rule::try_rewrite`], expanding `%{VARIABLE}` references in the
+    /// substitution using the [`EngineCtx`] along with `%N` references to
+    /// the captures of the last matched `RewriteCond` regex.
+    ///
+    /// Variables, `%N` and `$N` references are expanded in a single pass, so
+    /// a value containing `$1` or `%1` is inserted literally rather
+    /// than being expanded again.
+    ///
+    /// Environment variables declared by `[E=VAR:VAL]` flags are assigned
+    /// to the [`EngineCtx`] once the substitution is expanded, where the
+    /// value supports `%{VARIABLE}`, `%N` and `$N` references.
+    pub fn try_rewrite_ctx(&self, uri: &str, ctx: &mut EngineCtx) -> Option<String> {
         let mut caps = self.pattern.create_captures();
         self.pattern.captures(uri, &mut caps);
         if !caps.is_match() {
             return None;
         }
 
-        let noescape = self
-            .flags
-            .iter()
-            .any(|f| matches!(f, RuleFlag::Mod(RuleMod::NoEscape)));
+        let has = |flag: &RuleMod| {
+            self.flags
+                .iter()
+                .any(|f| matches!(f, RuleFlag::Mod(m) if m == flag))
+        };
+        let escape = match (has(&RuleMod::EscapeBackrefs), has(&RuleMod::NoEscape)) {
+            (true, _) => Escape::Backrefs,
+            (false, true) => Escape::None,
+            (false, false) => Escape::Default,
+        };
+
+        let dst = match self.is_passthrough() {
+            true => uri.to_owned(),
+            false => self.substitute(uri, &caps, escape, ctx),
+        };
+        for (name, value) in self.env() {
+            let value = value.map(|value| {
+                let mut expanded = String::new();
+                ctx.expand_into(
+                    value,
+                    |text, dst| interpolate(text, uri, &caps, Escape::None, dst),
+                    &mut expanded,
+                );
+                expanded
+            });
+            ctx.set_env(name, value.as_deref());
+        }
+        Some(dst)
+    }
 
+    /// Expand the substitution of the rule for the matched uri.
+    fn substitute(
+        &self,
+        uri: &str,
+        caps: &util::captures::Captures,
+        escape: Escape,
+        ctx: &mut EngineCtx,
+    ) -> String {
+        let expand = |ctx: &mut EngineCtx, text: &str, escape: Escape, dst: &mut String| {
+            ctx.expand_into(
+                text,
+                |text, dst| interpolate(text, uri, caps, escape, dst),
+                dst,
+            )
+        };
         let mut dst = String::new();
-        util::interpolate::string(
-            &self.rewrite,
-            |index, dst| {
-                let string = match caps.get_group(index) {
-                    None => return,
-                    Some(span) => &uri[span],
-                };
-                if noescape {
-                    return dst.push_str(string);
+        // set once the text of the substitution reached its query string
+        let mut query = false;
+        for segment in map::segments(&self.rewrite, |name| ctx.has_map(name)) {
+            match segment {
+                Segment::Text(text) if query || !matches!(escape, Escape::Default) => {
+                    expand(ctx, text, escape, &mut dst)
                 }
-                let s = utf8_percent_encode(string, ESCAPE).to_string();
-                dst.push_str(&s);
-            },
-            |name| caps.group_info().to_index(caps.pattern()?, name),
-            &mut dst,
-        );
-        Some(dst)
+                Segment::Text(text) => match text.split_once('?') {
+                    Some((path, rest)) => {
+                        expand(ctx, path, Escape::Path, &mut dst);
+                        dst.push('?');
+                        expand(ctx, rest, escape, &mut dst);
+                        query = true;
+                    }
+                    None => expand(ctx, text, Escape::Path, &mut dst),
+                },
+                Segment::Call(func, arg) => {
+                    let mut value = String::new();
+                    expand(ctx, arg, Escape::None, &mut value);
+                    dst.push_str(&func.apply(&value));
+                }
+                Segment::Lookup(name, arg) => {
+                    let mut value = String::new();
+                    expand(ctx, arg, Escape::None, &mut value);
+                    dst.push_str(&ctx.lookup(name, &value));
+                }
+            }
+        }
+        dst
+    }
+
+    /// Returns true if the substitution is `-`, leaving the uri unchanged.
+    #[inline]
+    pub(crate) fn is_passthrough(&self) -> bool {
+        self.rewrite == "-"
+    }
+
+    /// Returns true if the rule pattern matches the uri.
+    #[inline]
+    pub(crate) fn is_match(&self, uri: &str) -> bool {
+        self.pattern.is_match(uri)
+    }
+
+    /// Try to match the rewrite expression pattern to the specified uri
+    /// without performing any substitution.
+    ///
+    /// Produces the positional captures, starting with the full match,
+    /// along with all named captures that participated in the match.
+    pub(crate) fn try_match(&self, uri: &str) -> Option<Captures> {
+        let mut caps = self.pattern.create_captures();
+        self.pattern.captures(uri, &mut caps);
+        if !caps.is_match() {
+            return None;
+        }
+        let captures = (0..caps.group_len())
+            .map(|i| caps.get_group(i).map(|span| uri[span.range()].to_owned()))
+            .collect();
+        let named = self
+            .pattern
+            .group_info()
+            .pattern_names(PatternID::ZERO)
+            .enumerate()
+            .filter_map(|(i, name)| Some((name?.to_owned(), caps.get_group(i)?)))
+            .map(|(name, span)| (name, uri[span.range()].to_owned()))
+            .collect();
+        Some((captures, named))
+    }
+
+    /// Heap memory used by the compiled pattern in bytes.
+    #[inline]
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.pattern.memory_usage()
+    }
+
+    /// Retrieve the substitution string as written.
+    #[inline]
+    pub fn substitution(&self) -> &str {
+        &self.rewrite
+    }
+
+    /// Iterate over the names of all `%{VARIABLE}` references expanded
+    /// within the substitution.
+    #[inline]
+    pub(crate) fn variables(&self) -> impl Iterator<Item = &str> {
+        context::variables(&self.rewrite)
     }
 
     /// Retrieves the associated [`RuleShift`] defined in the
@@ -97,6 +397,46 @@ impl Rule {
         })
     }
 
+    /// Returns true if the rule merges the current query string into
+    /// the query of its substitution using `[QSA]`.
+    #[inline]
+    pub(crate) fn query_append(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|f| matches!(f, RuleFlag::Mod(RuleMod::QueryAppend)))
+    }
+
+    /// Returns true if the rule is chained to the following one using `[C]`.
+    #[inline]
+    pub(crate) fn is_chained(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|f| matches!(f, RuleFlag::Mod(RuleMod::Chain)))
+    }
+
+    /// Retrieve all environment variable assignments declared by `[E=...]`
+    /// flags, where `None` unsets the variable.
+    #[inline]
+    pub(crate) fn env(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.flags.iter().filter_map(|f| match f {
+            RuleFlag::Mod(RuleMod::Env(name, value)) => Some((name.as_str(), value.as_deref())),
+            _ => None,
+        })
+    }
+
+    /// Retrieve the first pass of the group rewrite loop the rule
+    /// is allowed to apply on.
+    #[inline]
+    pub(crate) fn pass(&self) -> u16 {
+        self.flags
+            .iter()
+            .find_map(|f| match f {
+                RuleFlag::Mod(RuleMod::Pass(pass)) => Some(*pass),
+                _ => None,
+            })
+            .unwrap_or(1)
+    }
+
     /// Retrieve the associated [`RuleResolve`] defined in the
     /// expressions flags if any is present.
     #[inline]
@@ -108,74 +448,261 @@ impl Rule {
     }
 }
 
-impl FromStr for Rule {
-    type Err = RuleError;
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.effective == other.effective
+            && self.rewrite == other.rewrite
+            && self.flags == other.flags
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut items = s.split_whitespace().filter(|s| !s.is_empty());
-        let pattern = items.next().ok_or(RuleError::MissingPattern)?;
-        let rewrite = items.next().ok_or(RuleError::MissingRewrite)?.to_string();
-        let flags = match items.next() {
-            Some(flags) => RuleFlagList::from_str(flags)?.0,
-            None => Vec::new(),
-        };
-        if let Some(next) = items.next() {
-            return Err(RuleError::InvalidSuffix(next.to_owned()));
+/// Serialized form of a [`Rule`], recompiled from the pattern source
+/// and flags when deserialized.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RuleRepr {
+    pattern: String,
+    #[serde(default)]
+    anchored: bool,
+    substitution: String,
+    #[serde(default)]
+    flags: Vec<RuleFlag>,
+    #[serde(default)]
+    unknown: Vec<String>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Rule> for RuleRepr {
+    fn from(rule: Rule) -> Self {
+        Self {
+            pattern: rule.source,
+            anchored: rule.anchored,
+            substitution: rule.rewrite,
+            flags: rule.flags,
+            unknown: rule.unknown,
         }
+    }
+}
 
-        let insense = flags
-            .iter()
-            .any(|f| matches!(f, RuleFlag::Mod(RuleMod::NoCase)));
-        let regex = Regex::builder()
-            .configure(
-                meta::Config::new()
-                    .nfa_size_limit(Some(10 * (1 << 20)))
-                    .hybrid_cache_capacity(2 * (1 << 20))
-                    .match_kind(MatchKind::LeftmostFirst)
-                    .utf8_empty(true),
-            )
-            .syntax(util::syntax::Config::new().case_insensitive(insense))
-            .build(pattern)
-            .map_err(|err| RuleError::InvalidRegex(err.to_string()))?;
+#[cfg(feature = "serde")]
+impl TryFrom<RuleRepr> for Rule {
+    type Error = RuleError;
 
+    fn try_from(repr: RuleRepr) -> Result<Self, Self::Error> {
+        check_flags(&repr.flags)?;
+        let (effective, pattern) = compile(&repr.pattern, &repr.flags, repr.anchored)?;
         Ok(Self {
-            pattern: regex,
-            rewrite,
-            flags,
+            source: repr.pattern,
+            anchored: repr.anchored,
+            effective,
+            pattern,
+            rewrite: repr.substitution,
+            flags: repr.flags,
+            unknown: repr.unknown,
         })
     }
 }
 
-struct RuleFlagList(Vec<RuleFlag>);
-
-impl FromStr for RuleFlagList {
+impl FromStr for Rule {
     type Err = RuleError;
 
+    #[inline]
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if !s.starts_with('[') || !s.ends_with(']') {
-            return Err(RuleError::FlagsMissingBrackets(s.to_owned()));
+        Self::parse(s, ParseMode::Strict)
+    }
+}
+
+/// Returns true if the template placeholder is a valid capture name.
+#[inline]
+fn is_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Escape a literal template character for use within a regex.
+#[inline]
+/// Replace `:name` placeholders declared in `names` with `${name}`
+/// backreferences, leaving longer words sharing the prefix untouched.
+fn substitute_names(rewrite: &str, names: &[&str]) -> String {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut dst = String::with_capacity(rewrite.len());
+    let mut rest = rewrite;
+    while let Some(i) = rest.find(':') {
+        dst.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let len = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+        let name = &after[..len];
+        match names.contains(&name) {
+            true => dst.push_str(&format!("${{{name}}}")),
+            false => {
+                dst.push(':');
+                dst.push_str(name);
+            }
         }
-        let flags = s[1..s.len() - 1]
-            .split(',')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .map(RuleFlag::from_str)
-            .collect::<Result<Vec<RuleFlag>, _>>()?;
-        if flags.is_empty() {
-            return Err(RuleError::FlagsEmpty);
+        rest = &after[len..];
+    }
+    dst.push_str(rest);
+    dst
+}
+
+fn escape_char(c: char) -> impl Iterator<Item = char> {
+    let meta = r"\.+*?()|[]{}^$#&-~".contains(c);
+    meta.then_some('\\').into_iter().chain(std::iter::once(c))
+}
+
+/// Normalize the pattern according to the flags and options of the rule
+/// and compile it, returning the effective source along with the regex.
+fn compile(source: &str, flags: &[RuleFlag], anchored: bool) -> Result<(String, Regex), RuleError> {
+    let mut effective = String::with_capacity(source.len() + 8);
+    if flags
+        .iter()
+        .any(|f| matches!(f, RuleFlag::Mod(RuleMod::NoCase)))
+    {
+        effective.push_str("(?i)");
+    }
+    match anchored && !source.starts_with('^') {
+        true => effective.push_str(&format!("^(?:{source})")),
+        false => effective.push_str(source),
+    }
+    let regex = Regex::builder()
+        .configure(
+            meta::Config::new()
+                .nfa_size_limit(Some(10 * (1 << 20)))
+                .hybrid_cache_capacity(2 * (1 << 20))
+                .match_kind(MatchKind::LeftmostFirst)
+                .utf8_empty(true),
+        )
+        .build(&effective)
+        .map_err(|err| RuleError::InvalidRegex(err.to_string()))?;
+    Ok((effective, regex))
+}
+
+/// Compile the patterns of all rules into a single multi-pattern regex
+/// reporting which rules match a uri, where each pattern id is the index
+/// of the rule.
+///
+/// Returns `None` for fewer than two rules or when the combined patterns
+/// exceed the size limits, in which case rules are matched one at a time.
+pub(crate) fn compile_set(rules: &[Rule]) -> Option<Regex> {
+    if rules.len() < 2 {
+        return None;
+    }
+    let patterns: Vec<&str> = rules.iter().map(|r| r.effective.as_str()).collect();
+    Regex::builder()
+        .configure(
+            meta::Config::new()
+                .nfa_size_limit(Some(10 * (1 << 20)))
+                .hybrid_cache_capacity(2 * (1 << 20))
+                .match_kind(MatchKind::All)
+                .utf8_empty(true),
+        )
+        .build_many(&patterns)
+        .ok()
+}
+
+/// Expand capture group backreferences within the template into `dst`.
+#[inline]
+fn interpolate(
+    template: &str,
+    uri: &str,
+    caps: &util::captures::Captures,
+    escape: Escape,
+    dst: &mut String,
+) {
+    util::interpolate::string(
+        template,
+        |index, dst| {
+            let string = match caps.get_group(index) {
+                None => return,
+                Some(span) => &uri[span],
+            };
+            match escape {
+                Escape::None => dst.push_str(string),
+                Escape::Default => escape_value(string, ESCAPE, dst),
+                Escape::Path => escape_value(string, PATH_ESCAPE, dst),
+                Escape::Backrefs => dst.extend(utf8_percent_encode(string, BACKREF_ESCAPE)),
+            }
+        },
+        |name| caps.group_info().to_index(caps.pattern()?, name),
+        dst,
+    );
+}
+
+/// Percent-encode a backreference value into `dst`.
+///
+/// The uri is matched in its encoded form, so existing `%XX` escapes are
+/// kept as-is and only a `%` not starting a valid escape is encoded.
+fn escape_value(value: &str, set: &'static AsciiSet, dst: &mut String) {
+    let mut rest = value;
+    while let Some(i) = rest.find('%') {
+        dst.extend(utf8_percent_encode(&rest[..i], set));
+        let valid = rest
+            .as_bytes()
+            .get(i + 1..i + 3)
+            .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        dst.push_str(if valid { "%" } else { "%25" });
+        rest = &rest[i + 1..];
+    }
+    dst.extend(utf8_percent_encode(rest, set));
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", quote(&self.source), quote(&self.rewrite))?;
+        if !self.flags.is_empty() {
+            let flags: Vec<_> = self.flags.iter().map(|f| f.to_string()).collect();
+            write!(f, " [{}]", flags.join(","))?;
         }
-        let num_meta = flags
-            .iter()
-            .filter(|f| matches!(f, RuleFlag::Shift(_)))
-            .count();
-        let num_response = flags
-            .iter()
-            .filter(|f| matches!(f, RuleFlag::Resolve(_)))
-            .count();
-        if (num_meta + num_response) > 1 {
-            return Err(RuleError::FlagsMutuallyExclusive);
+        Ok(())
+    }
+}
+
+/// Parse a bracketed flag list into the known flags along with the
+/// unknown flags skipped in [`ParseMode::Lenient`].
+fn parse_flags(s: &str, mode: ParseMode) -> Result<(Vec<RuleFlag>, Vec<String>), RuleError> {
+    if !s.starts_with('[') || !s.ends_with(']') {
+        return Err(RuleError::FlagsMissingBrackets(s.to_owned()));
+    }
+    let mut flags = Vec::new();
+    let mut unknown = Vec::new();
+    for flag in s[1..s.len() - 1]
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+    {
+        match RuleFlag::from_str(flag) {
+            Ok(flag) => flags.push(flag),
+            Err(RuleError::InvalidFlag(flag)) if mode == ParseMode::Lenient => unknown.push(flag),
+            Err(err) => return Err(err),
         }
-        Ok(Self(flags))
+    }
+    if flags.is_empty() && unknown.is_empty() {
+        return Err(RuleError::FlagsEmpty);
+    }
+    check_flags(&flags)?;
+    Ok((flags, unknown))
+}
+
+/// Reject flag combinations that contradict each other, such as `[R,F]`.
+fn check_flags(flags: &[RuleFlag]) -> Result<(), RuleError> {
+    let num_meta = flags
+        .iter()
+        .filter(|f| matches!(f, RuleFlag::Shift(_)))
+        .count();
+    let num_response = flags
+        .iter()
+        .filter(|f| matches!(f, RuleFlag::Resolve(_)))
+        .count();
+    // responses already stop processing, making `[L]` and `[END]` redundant
+    let num_continue = flags
+        .iter()
+        .filter(|f| matches!(f, RuleFlag::Shift(RuleShift::Next | RuleShift::Skip(_))))
+        .count();
+    match num_meta > 1 || num_response > 1 || (num_response > 0 && num_continue > 0) {
+        true => Err(RuleError::FlagsMutuallyExclusive),
+        false => Ok(()),
     }
 }
 
@@ -187,6 +714,25 @@ fn parse_int(s: &str, default: u16) -> Result<u16, RuleError> {
     }
 }
 
+#[inline]
+fn parse_redirect(s: &str) -> Result<RuleResolve, RuleError> {
+    match s.to_lowercase().as_str() {
+        "permanent" => Ok(RuleResolve::PermanentRedirect),
+        "temp" => Ok(RuleResolve::TemporaryRedirect),
+        "seeother" => Ok(RuleResolve::Redirect(303)),
+        _ => Ok(RuleResolve::Redirect(parse_status(s, 302)?)),
+    }
+}
+
+#[inline]
+fn parse_env(s: &str) -> RuleMod {
+    if let Some(name) = s.strip_prefix('!') {
+        return RuleMod::Env(name.to_owned(), None);
+    }
+    let (name, value) = s.split_once(':').unwrap_or((s, ""));
+    RuleMod::Env(name.to_owned(), Some(value.to_owned()))
+}
+
 #[inline]
 fn parse_status(s: &str, default: u16) -> Result<u16, RuleError> {
     let status = parse_int(s, default)?;
@@ -197,59 +743,146 @@ fn parse_status(s: &str, default: u16) -> Result<u16, RuleError> {
 }
 
 /// [`RuleFlag`] subtype declaring shift in rule processing after match
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RuleShift {
+    /// `[END]` stops evaluating rules within every group.
     End,
+    /// `[L]` stops evaluating rules within the current group.
     Last,
+    /// `[N]` restarts evaluation from the first rule of the group with the
+    /// rewritten uri.
+    ///
+    /// Every restart counts against the group's iteration limit, so a rule
+    /// whose substitution keeps matching fails with
+    /// [`EngineError::TooManyIterations`](crate::error::EngineError::TooManyIterations)
+    /// rather than looping forever.
     Next,
+    /// `[S=N]` skips the following `N` rules of the group.
     Skip(u16),
 }
 
 /// [`RuleFlag`] subtype declaring a modification in rewrite behavior
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RuleMod {
     NoCase,
     NoEscape,
+    /// Percent-encode every reserved character within backreference
+    /// values, including existing escapes, before substitution.
+    EscapeBackrefs,
+    /// Non-Apache extension that suppresses the rule until the
+    /// group has restarted (via `[N]`) enough times to reach the pass.
+    Pass(u16),
+    /// Append the current query string after the query of the substitution
+    /// rather than replacing it.
+    QueryAppend,
+    /// Chain the rule to the following one, skipping every following
+    /// member of the chain when the rule does not match.
+    Chain,
+    /// Assign (`E=VAR:VAL`) or unset (`E=!VAR`) an environment variable
+    /// visible as `%{ENV:VAR}` to later rules and groups.
+    Env(String, Option<String>),
 }
 
 /// [`RuleFlag`] subtype declaring a final http-response resolution
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RuleResolve {
     Redirect(u16),
+    /// Redirect declared as `R=permanent`, using `301` or `308` when
+    /// method-preserving redirects are enabled.
+    PermanentRedirect,
+    /// Redirect declared as `R=temp`, using `302` or `307` when
+    /// method-preserving redirects are enabled.
+    TemporaryRedirect,
     Status(u16),
 }
 
+impl RuleResolve {
+    /// Returns true if the resolution is any kind of redirect.
+    #[inline]
+    pub(crate) fn is_redirect(&self) -> bool {
+        !matches!(self, Self::Status(_))
+    }
+
+    /// Retrieve the http status of the resolution, mapping redirect
+    /// keywords to their method-preserving equivalents if enabled.
+    pub(crate) fn status(&self, method_preserving: bool) -> u16 {
+        match (self, method_preserving) {
+            (Self::Redirect(status) | Self::Status(status), _) => *status,
+            (Self::PermanentRedirect, false) => 301,
+            (Self::PermanentRedirect, true) => 308,
+            (Self::TemporaryRedirect, false) => 302,
+            (Self::TemporaryRedirect, true) => 307,
+        }
+    }
+}
+
 /// Flag Modifiers to a [`Rule`] expression.
 ///
 /// Supports a subset of [official](https://httpd.apache.org/docs/current/rewrite/flags.html)
 /// `mod_rewrite` flags.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RuleFlag {
     Shift(RuleShift),
     Mod(RuleMod),
     Resolve(RuleResolve),
 }
 
+impl fmt::Display for RuleFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Shift(RuleShift::End) => write!(f, "END"),
+            Self::Shift(RuleShift::Last) => write!(f, "L"),
+            Self::Shift(RuleShift::Next) => write!(f, "N"),
+            Self::Shift(RuleShift::Skip(n)) => write!(f, "S={n}"),
+            Self::Mod(RuleMod::NoCase) => write!(f, "NC"),
+            Self::Mod(RuleMod::NoEscape) => write!(f, "NE"),
+            Self::Mod(RuleMod::EscapeBackrefs) => write!(f, "B"),
+            Self::Mod(RuleMod::Pass(n)) => write!(f, "pass={n}"),
+            Self::Mod(RuleMod::QueryAppend) => write!(f, "QSA"),
+            Self::Mod(RuleMod::Chain) => write!(f, "C"),
+            Self::Mod(RuleMod::Env(name, None)) => write!(f, "E=!{name}"),
+            Self::Mod(RuleMod::Env(name, Some(value))) if value.is_empty() => write!(f, "E={name}"),
+            Self::Mod(RuleMod::Env(name, Some(value))) => write!(f, "E={name}:{value}"),
+            Self::Resolve(RuleResolve::Redirect(status)) => write!(f, "R={status}"),
+            Self::Resolve(RuleResolve::PermanentRedirect) => write!(f, "R=permanent"),
+            Self::Resolve(RuleResolve::TemporaryRedirect) => write!(f, "R=temp"),
+            Self::Resolve(RuleResolve::Status(403)) => write!(f, "F"),
+            Self::Resolve(RuleResolve::Status(410)) => write!(f, "G"),
+            Self::Resolve(RuleResolve::Status(status)) => write!(f, "={status}"),
+        }
+    }
+}
+
 impl FromStr for RuleFlag {
     type Err = RuleError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let flag = s;
         let (p, s) = match s.split_once('=') {
             Some((prefix, suffix)) => (prefix, suffix),
             None => (s, ""),
         };
         match p.to_lowercase().as_str() {
+            "b" => Ok(Self::Mod(RuleMod::EscapeBackrefs)),
+            "c" | "chain" => Ok(Self::Mod(RuleMod::Chain)),
+            "e" | "env" if !s.is_empty() => Ok(Self::Mod(parse_env(s))),
             "e" | "end" => Ok(Self::Shift(RuleShift::End)),
             "l" | "last" => Ok(Self::Shift(RuleShift::Last)),
             "n" | "next" => Ok(Self::Shift(RuleShift::Next)),
             "s" | "skip" => Ok(Self::Shift(RuleShift::Skip(parse_int(s, 1)?))),
             "i" | "insensitive" | "nc" | "nocase" => Ok(Self::Mod(RuleMod::NoCase)),
             "ne" | "noescape" => Ok(Self::Mod(RuleMod::NoEscape)),
-            "r" | "redirect" => Ok(Self::Resolve(RuleResolve::Redirect(parse_status(s, 302)?))),
+            "pass" => Ok(Self::Mod(RuleMod::Pass(parse_int(s, 1)?))),
+            "qsa" | "qsappend" => Ok(Self::Mod(RuleMod::QueryAppend)),
+            "r" | "redirect" => Ok(Self::Resolve(parse_redirect(s)?)),
             "f" | "forbidden" => Ok(Self::Resolve(RuleResolve::Status(403))),
             "g" | "gone" => Ok(Self::Resolve(RuleResolve::Status(410))),
             "" => Ok(Self::Resolve(RuleResolve::Status(parse_status(s, 403)?))),
-            _ => Err(RuleError::InvalidFlag(s.to_owned())),
+            _ => Err(RuleError::InvalidFlag(flag.to_owned())),
         }
     }
 }
@@ -258,13 +891,37 @@ impl FromStr for RuleFlag {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_named_backrefs() {
+        let rule = Rule::from_str(r"^/(?P<user>\w+)/profile /u?name=${user}").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/alice/profile"),
+            Some("/u?name=alice".to_owned())
+        );
+
+        let rule = Rule::from_str(r"^/(\w+)/(?P<page>[^/]+)$ /$1/view?page=${page}").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/bob/home"),
+            Some("/bob/view?page=home".to_owned())
+        );
+
+        let rule = Rule::from_str(r"^/(?P<user>\w+)$ /u?name=${missing}").unwrap();
+        assert_eq!(rule.try_rewrite("/alice"), Some("/u?name=".to_owned()));
+
+        let rule = Rule::from_str(r"^/s/(?P<q>.*)$ /search?q=${q} [B]").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/s/a b&c"),
+            Some("/search?q=a%20b%26c".to_owned())
+        );
+    }
+
     #[test]
     fn test_compile() {
         let rule = Rule::from_str(" ^/replace/[A-Z]+/$ - [I,F]").unwrap();
         assert_eq!(rule.rewrite, "-".to_owned());
         assert_eq!(rule.flags.len(), 2);
         assert!(matches!(
-            rule.flags.get(0),
+            rule.flags.first(),
             Some(RuleFlag::Mod(RuleMod::NoCase))
         ));
         assert!(matches!(
@@ -273,6 +930,180 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_missing_rewrite() {
+        assert!(matches!(
+            Rule::from_str(" ^/a$ "),
+            Err(RuleError::MissingRewrite)
+        ));
+        assert!(matches!(
+            Rule::from_str(" ^/a$ [L]"),
+            Err(RuleError::MissingRewrite)
+        ));
+    }
+
+    #[test]
+    fn test_equality() {
+        let rule = Rule::from_str(r" ^/file/(.*)$ /new/$1 [NE,L]").unwrap();
+        assert_eq!(
+            rule,
+            Rule::from_str(r"^/file/(.*)$   /new/$1  [NE,L]").unwrap()
+        );
+        assert_ne!(rule, Rule::from_str(r" ^/file/(.*)$ /new/$1 [NE]").unwrap());
+        assert_ne!(
+            rule,
+            Rule::from_str(r" ^/file/(.+)$ /new/$1 [NE,L]").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_env_flag() {
+        let rule = Rule::from_str("^/a$ /b [E=LANG:fr,E=!OLD,env=EMPTY]").unwrap();
+        assert_eq!(
+            rule.env().collect::<Vec<_>>(),
+            vec![("LANG", Some("fr")), ("OLD", None), ("EMPTY", Some(""))]
+        );
+        assert_eq!(rule.to_string(), "^/a$ /b [E=LANG:fr,E=!OLD,E=EMPTY]");
+
+        let rule = Rule::from_str("^/a$ /b [E]").unwrap();
+        assert_eq!(rule.shift(), Some(&RuleShift::End));
+    }
+
+    #[test]
+    fn test_variables() {
+        use crate::conditions::context::RequestCtx;
+
+        let rule =
+            Rule::from_str("^/go/(.*) /dest/$1?host=%{HTTP_HOST} [E=FROM:%{HTTP_HOST}$1]").unwrap();
+        let req = RequestCtx::default().http_host("$1.example.com");
+        let mut ctx = EngineCtx::default().with_ctx(req);
+        assert_eq!(
+            rule.try_rewrite_ctx("/go/%{HTTP_HOST}", &mut ctx)
+                .as_deref(),
+            Some("/dest/%25%7BHTTP_HOST%7D?host=$1.example.com")
+        );
+        assert_eq!(ctx.fill("ENV:FROM"), "$1.example.com%{HTTP_HOST}");
+    }
+
+    #[test]
+    fn test_quoted() {
+        let rule = Rule::from_str(r#""^/my docs/(.*)" '/files/a b/$1' [NE]"#).unwrap();
+        assert_eq!(rule.pattern_str(), "^/my docs/(.*)");
+        assert_eq!(rule.substitution(), "/files/a b/$1");
+        assert_eq!(
+            rule.try_rewrite("/my docs/x"),
+            Some("/files/a b/x".to_owned())
+        );
+        assert_eq!(rule.to_string(), r#""^/my docs/(.*)" "/files/a b/$1" [NE]"#);
+        assert_eq!(Rule::from_str(&rule.to_string()).unwrap(), rule);
+
+        let rule = Rule::from_str(r#"^/q "/say \"hi\" now""#).unwrap();
+        assert_eq!(rule.substitution(), r#"/say "hi" now"#);
+        assert_eq!(Rule::from_str(&rule.to_string()).unwrap(), rule);
+
+        let engine = crate::Engine::from_str(r#"RewriteRule "^/a b$" /c # comment"#).unwrap();
+        assert!(matches!(engine.rewrite("/a b"), Ok(crate::Rewrite::Uri(uri)) if uri == "/c"));
+
+        assert!(matches!(
+            Rule::from_str(r#"^/a "/b c"#),
+            Err(RuleError::UnclosedQuotation(s)) if s == r#""/b c"#
+        ));
+    }
+
+    #[test]
+    fn test_effective_pattern() {
+        let rule = Rule::from_str("/file/(.*) /new/$1").unwrap();
+        assert_eq!(rule.effective_pattern(), "/file/(.*)");
+        assert_eq!(rule.try_rewrite("/old/file/a"), Some("/new/a".to_owned()));
+
+        let rule = rule.anchored();
+        assert_eq!(rule.effective_pattern(), "^(?:/file/(.*))");
+        assert_eq!(rule.try_rewrite("/old/file/a"), None);
+        assert_eq!(rule.try_rewrite("/file/a"), Some("/new/a".to_owned()));
+
+        let rule = Rule::from_str("^/file /new [NC]").unwrap().anchored();
+        assert_eq!(rule.effective_pattern(), "(?i)^/file");
+        assert_eq!(rule.to_string(), "^/file /new [NC]");
+    }
+
+    #[test]
+    fn test_unknown_flags() {
+        let rule = Rule::parse("^/a$ /b [L,FUTUREFLAG]", ParseMode::Lenient).unwrap();
+        assert_eq!(rule.shift(), Some(&RuleShift::Last));
+        assert_eq!(rule.unknown_flags(), ["FUTUREFLAG"]);
+        assert_eq!(rule.to_string(), "^/a$ /b [L]");
+
+        assert!(matches!(
+            Rule::from_str("^/a$ /b [L,FUTUREFLAG]"),
+            Err(RuleError::InvalidFlag(flag)) if flag == "FUTUREFLAG"
+        ));
+        assert!(matches!(
+            Rule::parse("^/a$ /b [R=999,FUTUREFLAG]", ParseMode::Lenient),
+            Err(RuleError::InvalidFlagStatus(_))
+        ));
+
+        let flags = vec!["NC"; 10_000].join(",");
+        let rule = Rule::from_str(&format!("^/a$ /b [{flags},L]")).unwrap();
+        assert_eq!(rule.flags.len(), 10_001);
+    }
+
+    #[test]
+    fn test_from_template() {
+        let rule = Rule::from_template("/users/:id", "/profile?id=:id", "").unwrap();
+        assert_eq!(rule.effective_pattern(), "^/users/(?P<id>[^/]+)$");
+        assert_eq!(
+            rule.try_rewrite("/users/42"),
+            Some("/profile?id=42".to_owned())
+        );
+        assert_eq!(rule.try_rewrite("/users/42/posts"), None);
+        assert_eq!(rule.try_rewrite("/users/"), None);
+
+        let rule = Rule::from_template(
+            "/v1.0/:org/:org_repo",
+            "https://example.com/:org_repo/:org",
+            "[R=301]",
+        )
+        .unwrap();
+        assert_eq!(
+            rule.try_rewrite("/v1.0/rust/regex"),
+            Some("https://example.com/regex/rust".to_owned())
+        );
+        assert_eq!(rule.try_rewrite("/v1x0/rust/regex"), None);
+        assert_eq!(rule.resolve(), Some(&RuleResolve::Redirect(301)));
+
+        let rule = Rule::from_template("/my files/:id", "/a?b=:identity&c=:id", "").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/my files/42"),
+            Some("/a?b=:identity&c=42".to_owned())
+        );
+        let rule = Rule::from_template("/:id", "\"/x y\"/:id", "").unwrap();
+        assert_eq!(rule.try_rewrite("/42"), Some("\"/x y\"/42".to_owned()));
+    }
+
+    #[test]
+    fn test_query_append_flag() {
+        for flags in ["[QSA]", "[qsappend]"] {
+            let rule = Rule::from_str(&format!("^/old$ /new?added=1 {flags}")).unwrap();
+            assert!(rule.query_append());
+            assert_eq!(rule.to_string(), "^/old$ /new?added=1 [QSA]");
+        }
+        assert!(!Rule::from_str("^/old$ /new").unwrap().query_append());
+    }
+
+    #[test]
+    fn test_display() {
+        for expr in [
+            "^/a$ /b",
+            "^/(.*)$ /new/$1 [NC,NE,R=301]",
+            "^/old - [G]",
+            "^/s /t [S=2]",
+            "^/p /q [R=permanent]",
+        ] {
+            let rule = Rule::from_str(expr).unwrap();
+            assert_eq!(rule.to_string(), expr);
+        }
+    }
+
     #[test]
     fn test_simple_replace() {
         let rule = Rule::from_str(r" ^/file/(.*)$ /new/$1 [NE]").unwrap();
@@ -299,6 +1130,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_function_replace() {
+        let rule = Rule::from_str(r" ^/file/(\w+)$ /new/${tolower:$1} ").unwrap();
+        assert_eq!(rule.try_rewrite("/file/ABC"), Some("/new/abc".to_owned()));
+
+        let rule = Rule::from_str(r" ^/file/(.*)$ /new/${escape:$1} ").unwrap();
+        assert_eq!(rule.try_rewrite("/file/a b"), Some("/new/a%20b".to_owned()));
+
+        let rule = Rule::from_str(r" ^/file/(?P<name>\w+)$ /${toupper:${name}}/$1 ").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/file/named"),
+            Some("/NAMED/named".to_owned())
+        );
+    }
+
     #[test]
     fn test_named_replace() {
         let rule = Rule::from_str(r" ^/file/(?P<name>\w+)$ /$name ").unwrap();
@@ -308,4 +1154,53 @@ mod tests {
             Some("/named_file".to_owned())
         );
     }
+
+    #[test]
+    fn test_escape() {
+        let rule = Rule::from_str(r" ^/s/(.*)$ /search?q=$1 ").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/s/a b?c#d%zz%41"),
+            Some("/search?q=a%20b%3Fc%23d%25zz%41".to_owned())
+        );
+
+        let rule = Rule::from_str(r" ^/s/(.*)$ /search?q=$1 [noescape]").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/s/a b?c#d%zz%41"),
+            Some("/search?q=a b?c#d%zz%41".to_owned())
+        );
+
+        let rule = Rule::from_str(r" ^/p/(.*)$ /files/$1?from=$1 ").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/p/a/b"),
+            Some("/files/a/b?from=a%2Fb".to_owned())
+        );
+
+        let rule = Rule::from_str(r" ^/p/(.*)$ /${tolower:A?}/$1 ").unwrap();
+        assert_eq!(rule.try_rewrite("/p/a/b"), Some("/a?/a/b".to_owned()));
+    }
+
+    #[test]
+    fn test_escape_backrefs() {
+        let rule = Rule::from_str(r" ^/tag/(.*)$ /search.php?tag=$1&page=1 [B]").unwrap();
+        assert_eq!(
+            rule.try_rewrite("/tag/c++/rust & go%21"),
+            Some("/search.php?tag=c%2B%2B%2Frust%20%26%20go%2521&page=1".to_owned())
+        );
+        assert_eq!(
+            rule.to_string(),
+            "^/tag/(.*)$ /search.php?tag=$1&page=1 [B]"
+        );
+    }
+
+    #[test]
+    fn test_exclusive_flags() {
+        assert!(Rule::from_str("^/a$ https://example.com/a [R=301,L]").is_ok());
+        assert!(Rule::from_str("^/a$ - [F,END]").is_ok());
+        for flags in ["[R,N]", "[F,S=1]", "[L,END]", "[R,F]"] {
+            assert!(matches!(
+                Rule::from_str(&format!("^/a$ /b {flags}")),
+                Err(RuleError::FlagsMutuallyExclusive)
+            ));
+        }
+    }
 }
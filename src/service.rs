@@ -0,0 +1,229 @@
+//! Middleware applying an [`Engine`] to the requests of a `tower` service.
+//!
+//! Requires the `tower` feature. Works with any stack built on `tower`,
+//! such as `axum` or `hyper`.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::{Request, Response, Uri, request::Parts};
+use pin_project_lite::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::conditions::EngineCtx;
+use super::context::{DateCtx, ServerCtx};
+use super::{Engine, Rewrite};
+
+/// [`Layer`] wrapping services in a [`RewriteService`] sharing the
+/// same [`Engine`].
+///
+/// # Example
+///
+/// ```
+/// use std::str::FromStr;
+/// use mod_rewrite::{Engine, RewriteLayer};
+///
+/// let engine = Engine::from_str("RewriteRule ^/old/(.*)$ /new/$1 [R=301]").unwrap();
+/// let layer = RewriteLayer::new(engine);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RewriteLayer {
+    engine: Arc<Engine>,
+}
+
+impl RewriteLayer {
+    /// Build a layer evaluating requests against the [`Engine`].
+    pub fn new(engine: impl Into<Arc<Engine>>) -> Self {
+        Self {
+            engine: engine.into(),
+        }
+    }
+}
+
+impl<S> Layer<S> for RewriteLayer {
+    type Service = RewriteService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RewriteService {
+            engine: Arc::clone(&self.engine),
+            inner,
+        }
+    }
+}
+
+/// [`Service`] rewriting the uri of each request before passing it
+/// to the inner service.
+///
+/// The [`EngineCtx`] is built from the method, uri and headers of the
+/// request by [`EngineCtx::from_http_request`]. A [`Rewrite::Uri`] or [`Rewrite::EndUri`]
+/// replaces the request uri, while [`Rewrite::Redirect`] and
+/// [`Rewrite::StatusCode`] respond immediately without calling the inner
+/// service. Failed rewrites respond with `500 Internal Server Error`.
+#[derive(Clone, Debug)]
+pub struct RewriteService<S> {
+    engine: Arc<Engine>,
+    inner: S,
+}
+
+impl<S> RewriteService<S> {
+    /// Evaluate the request, producing the rewritten uri or the
+    /// response to return immediately.
+    fn rewrite<B: Default>(&self, parts: &Parts) -> Result<Uri, Response<B>> {
+        let mut ctx = EngineCtx::from_http_request(parts, ServerCtx::default(), DateCtx::new());
+        match self.engine.rewrite_uri(&parts.uri, &mut ctx) {
+            Ok(Rewrite::Uri(uri) | Rewrite::EndUri(uri)) => {
+                Ok(uri.parse().expect("rewrite_uri produces valid uris"))
+            }
+            Ok(rewrite) => Err(respond(rewrite)),
+            Err(err) => {
+                log::warn!("failed to rewrite {}: {err}", parts.uri);
+                Err(respond(Rewrite::StatusCode(500)))
+            }
+        }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RewriteService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, ResBody>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
+        match self.rewrite(&parts) {
+            Ok(uri) => {
+                parts.uri = uri;
+                let future = self.inner.call(Request::from_parts(parts, body));
+                ResponseFuture::Inner { future }
+            }
+            Err(response) => ResponseFuture::Ready {
+                response: Some(response),
+            },
+        }
+    }
+}
+
+pin_project! {
+    /// Response future of a [`RewriteService`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F, B> {
+        /// Response of the inner service.
+        Inner { #[pin] future: F },
+        /// Redirect or status response produced by the engine.
+        Ready { response: Option<Response<B>> },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F, B>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future } => future.poll(cx),
+            ResponseFutureProj::Ready { response } => {
+                Poll::Ready(Ok(response.take().expect("polled after completion")))
+            }
+        }
+    }
+}
+
+/// Build the empty response of a redirect or status [`Rewrite`].
+fn respond<B: Default>(rewrite: Rewrite) -> Response<B> {
+    rewrite
+        .into_response()
+        .expect("uri rewrites are dispatched")
+        .map(|()| B::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, future::Ready, net::SocketAddr, str::FromStr, task::Waker};
+
+    use http::{StatusCode, header};
+
+    use super::*;
+
+    /// Inner service responding with the uri it received.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<()>> for Echo {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<()>) -> Self::Future {
+            std::future::ready(Ok(Response::new(req.uri().to_string())))
+        }
+    }
+
+    fn call(service: &mut RewriteService<Echo>, req: Request<()>) -> Response<String> {
+        let mut cx = Context::from_waker(Waker::noop());
+        let future = std::pin::pin!(service.call(req));
+        match future.poll(&mut cx) {
+            Poll::Ready(Ok(response)) => response,
+            _ => unreachable!("echo responds immediately"),
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+
+    #[test]
+    fn test_service() {
+        assert_send_sync::<RewriteLayer>();
+        assert_send_sync::<RewriteService<Echo>>();
+
+        let engine = Engine::from_str(
+            r#"
+            RewriteCond %{REMOTE_HOST} =10.0.0.1
+            RewriteRule ^ - [F]
+
+            RewriteCond %{HTTP:X-Legacy} =1
+            RewriteRule ^/api/(.*)$ /v1/$1 [L]
+
+            RewriteRule ^/old/(.*)$ /new/$1 [R=301]
+        "#,
+        )
+        .unwrap();
+        let mut service = RewriteLayer::new(engine).layer(Echo);
+
+        let req = Request::get("/api/users?page=2").header("X-Legacy", "1");
+        let response = call(&mut service, req.body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "/v1/users?page=2");
+
+        let response = call(&mut service, Request::get("/api/users").body(()).unwrap());
+        assert_eq!(response.body(), "/api/users");
+
+        let response = call(&mut service, Request::get("/old/page").body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "/new/page");
+        assert!(response.body().is_empty());
+
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let req = Request::get("/api/users").extension(addr);
+        let response = call(&mut service, req.body(()).unwrap());
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
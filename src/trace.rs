@@ -0,0 +1,44 @@
+//! Record of the rules evaluated by [`Engine::rewrite_trace`](super::Engine::rewrite_trace).
+//!
+//! Intended for debugging rewrite configurations, such as finding the
+//! rules forming a cycle reported as
+//! [`EngineError::TooManyIterations`](super::error::EngineError::TooManyIterations).
+
+use super::expr::Rewrite;
+use super::rule::RuleShift;
+
+/// Single step of rule processing recorded in a [`Trace`].
+///
+/// Rule indexes refer to the position of the rule within the group
+/// of the preceding [`TraceEvent::GroupMatched`] event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent {
+    /// The conditions of the group at the index were met.
+    GroupMatched { group: usize },
+    /// A rule matched, producing the substitution from its positional
+    /// captures, starting with the full match.
+    RuleMatched {
+        rule: usize,
+        captures: Vec<Option<String>>,
+        uri: String,
+    },
+    /// A shift flag such as `[L]`, `[N]` or `[S=N]` was applied.
+    Shift { rule: usize, shift: RuleShift },
+    /// A rule resolved to a redirect or status response.
+    Resolved { rule: usize, rewrite: Rewrite },
+}
+
+/// Ordered list of [`TraceEvent`]s produced by
+/// [`Engine::rewrite_trace`](super::Engine::rewrite_trace).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// Append an event to the trace.
+    #[inline]
+    pub(crate) fn push(&mut self, event: TraceEvent) {
+        self.events.push(event);
+    }
+}